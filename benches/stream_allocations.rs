@@ -0,0 +1,160 @@
+//! Streams `MD` frames of 1081 steps from an in-memory sensor and checks that `next_into` does
+//! not allocate once its buffers have grown. Run with `cargo bench`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    io::{self, Read, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+use urg_rust::{Transport, Urg, UrgPayload};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const STEPS: usize = 1081;
+
+fn checksum(data: &[u8]) -> u8 {
+    let sum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    (sum & 0b00111111) + 0x30
+}
+
+fn response(echo: &str, status: &str, lines: &[&[u8]]) -> Vec<u8> {
+    let mut res = format!("{echo}\n").into_bytes();
+    for data in [status.as_bytes()].iter().chain(lines) {
+        res.extend_from_slice(data);
+        res.push(checksum(data));
+        res.push(b'\n');
+    }
+    res.push(b'\n');
+    res
+}
+
+// Answers `VV`, `PP` and `MD`, then repeats the same scan frame forever.
+#[derive(Default)]
+struct ReplaySensor {
+    input: Vec<u8>,
+    output: Vec<u8>,
+    pos: usize,
+    frame: Vec<u8>,
+}
+
+impl Read for ReplaySensor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.output.len() {
+            if self.frame.is_empty() {
+                return Ok(0);
+            }
+            self.output.clear();
+            self.output.extend_from_slice(&self.frame);
+            self.pos = 0;
+        }
+        let n = (self.output.len() - self.pos).min(buf.len());
+        buf[..n].copy_from_slice(&self.output[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Write for ReplaySensor {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.input.extend_from_slice(buf);
+        while let Some(pos) = self.input.iter().position(|byte| *byte == b'\n') {
+            let line = String::from_utf8(self.input.drain(..=pos).collect()).unwrap();
+            let line = line.trim_end();
+            let cmd = line.split(';').next().unwrap();
+            let res = match cmd {
+                "VV" => response(
+                    line,
+                    "00",
+                    &[
+                        b"VEND:Hokuyo Automatic Co.,Ltd.;",
+                        b"PROD:SOKUIKI Sensor TOP-URG UTM-30LX;",
+                        b"FIRM:1.20.00(17/Mar./2010);",
+                        b"PROT:SCIP 2.0;",
+                        b"SERI:H0000000;",
+                    ],
+                ),
+                "PP" => response(
+                    line,
+                    "00",
+                    &[
+                        b"MODL:UTM-30LX;",
+                        b"DMIN:23;",
+                        b"DMAX:60000;",
+                        b"ARES:1440;",
+                        b"AMIN:0;",
+                        b"AMAX:1080;",
+                        b"AFRT:540;",
+                        b"SCAN:2400;",
+                    ],
+                ),
+                _ if cmd.starts_with("MD") => {
+                    let data = b"1Dh".repeat(STEPS);
+                    let mut lines: Vec<&[u8]> = vec![b"1Dh0"];
+                    lines.extend(data.chunks(64));
+                    self.frame = response(line, "99", &lines);
+                    response(line, "00", &[])
+                }
+                _ => response(line, "0E", &[]),
+            };
+            self.output.drain(..self.pos);
+            self.pos = 0;
+            self.output.extend_from_slice(&res);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for ReplaySensor {}
+
+fn main() {
+    let urg = Urg::from_transport(ReplaySensor::default()).unwrap();
+    let mut scans = urg
+        .get_distance_multi(0, STEPS as u32 - 1, 0, 0, 0)
+        .unwrap();
+    let mut payload = UrgPayload::default();
+    for _ in 0..10 {
+        scans.next_into(&mut payload).unwrap().unwrap();
+    }
+
+    let frames = 100_000;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..frames {
+        scans.next_into(&mut payload).unwrap().unwrap();
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    assert_eq!(payload.distance.len(), STEPS);
+    println!(
+        "{frames} frames of {STEPS} steps in {elapsed:?} ({:?} per frame), {allocations} allocations",
+        elapsed / frames
+    );
+    assert_eq!(allocations, 0, "next_into allocated in steady state");
+}
@@ -0,0 +1,27 @@
+use futures_core::Stream;
+use std::{future, pin::Pin};
+use urg_rust::AsyncUrg;
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut urg = AsyncUrg::open("192.168.0.10".parse().unwrap(), 10940)
+            .await
+            .unwrap();
+        urg.start_capture().await.unwrap();
+        println!("{:?}", urg.get_status_info().await.unwrap());
+
+        let mut scans = urg.get_distance_multi(0, 1080, 0, 0, 10).await.unwrap();
+        while let Some(res) = future::poll_fn(|cx| Pin::new(&mut scans).poll_next(cx)).await {
+            match res {
+                Ok(payload) => println!("{} {:?}", payload.time_stamp, payload.distance),
+                Err(err) => println!("{}", err),
+            }
+        }
+
+        urg.stop_capture().await.unwrap();
+    });
+}
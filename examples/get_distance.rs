@@ -8,6 +8,7 @@ fn main() {
     let UrgPayload {
         time_stamp,
         distance,
+        extended_time_stamp: _,
         intensity: _,
     } = urg.get_distance(0, 1080, 0).unwrap();
     println!("{}", time_stamp);
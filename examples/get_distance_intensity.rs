@@ -7,6 +7,7 @@ fn main() {
 
     let UrgPayload {
         time_stamp,
+        extended_time_stamp: _,
         distance,
         intensity,
     } = urg.get_distance_intensity(0, 1080, 0).unwrap();
@@ -0,0 +1,4 @@
+fn main() {
+    let urg = urg_rust::Urg::open_serial("/dev/ttyACM0", 115200).unwrap();
+    println!("{:?}", urg.get_version_info());
+}
@@ -0,0 +1,19 @@
+use std::{thread, time::Duration};
+use urg_rust::{OverflowPolicy, ScanStream};
+
+fn main() {
+    let urg = urg_rust::Urg::open("192.168.0.10".parse().unwrap(), 10940).unwrap();
+    let scans = urg.get_distance_multi(0, 1080, 0, 0, 0).unwrap();
+    let stream = ScanStream::spawn(scans, 8, OverflowPolicy::DropOldest);
+
+    // A control loop at 10 Hz only looks at the newest scan.
+    for _ in 0..50 {
+        if let Some(payload) = stream.latest() {
+            println!("{} {:?}", payload.time_stamp, &payload.distance[535..545]);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    println!("dropped {} scans", stream.dropped_count());
+    stream.stop().unwrap();
+}
@@ -0,0 +1,14 @@
+fn main() {
+    let mut urg = urg_rust::Urg::open("192.168.0.10".parse().unwrap(), 10940).unwrap();
+    let time_sync = urg.sync_time(10).unwrap();
+    println!("{:?}", time_sync);
+
+    urg.start_capture().unwrap();
+    let payload = urg.get_distance(0, 1080, 0).unwrap();
+    println!("{}", payload.time_stamp);
+    println!(
+        "{:?}",
+        time_sync.to_system_time_extended(payload.extended_time_stamp)
+    );
+    urg.stop_capture().unwrap();
+}
@@ -0,0 +1,777 @@
+use crate::{
+    check_scip2_switch_response, check_send_cmd_response, decode_multi_echo, get_raw_data,
+    is_scip1_response, lock, next_tag, recv_fields, recv_scan_into, FrameQueue, InterfaceType,
+    ScipProtocol, TimeStampTracker, UrgCapabilities, UrgError, UrgMultiEchoPayload, UrgPayload,
+    UrgSensorParams, UrgStatusInfo, UrgVersionInfo,
+};
+use bstr::ByteSlice;
+use futures_core::Stream;
+use std::{
+    future::Future,
+    io,
+    net::IpAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{ready, Context, Poll},
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    runtime::Handle,
+    sync::Mutex as AsyncMutex,
+};
+
+/// `Urg` on tokio. Responses are read without blocking and then parsed exactly like those of
+/// `Urg`.
+///
+/// Unlike `Urg`, there are no `UrgTimeouts`; bound calls with `tokio::time::timeout` instead.
+#[derive(Debug)]
+pub struct AsyncUrg<T: AsyncRead + AsyncWrite + Unpin + Send + 'static = TcpStream> {
+    stream: Arc<AsyncMutex<BufReader<T>>>,
+    frames: Arc<Mutex<FrameQueue>>,
+    time_stamp_tracker: Arc<Mutex<TimeStampTracker>>,
+    negotiated_protocol: ScipProtocol,
+    is_capturing: Arc<AtomicBool>,
+    capabilities: Mutex<Option<UrgCapabilities>>,
+    interface: InterfaceType,
+}
+
+impl AsyncUrg {
+    pub async fn open(ip_address: IpAddr, port: u16) -> Result<Self, UrgError> {
+        let stream = TcpStream::connect((ip_address, port)).await?;
+        Self::with_interface(stream, InterfaceType::Ethernet).await
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> AsyncUrg<T> {
+    /// Connects over `transport`, switching a sensor that booted in SCIP 1.1 to SCIP 2.0.
+    pub async fn from_transport(transport: T) -> Result<Self, UrgError> {
+        Self::with_interface(transport, InterfaceType::Other).await
+    }
+
+    async fn with_interface(transport: T, interface: InterfaceType) -> Result<Self, UrgError> {
+        let mut reader = BufReader::new(transport);
+        let mut raw = Vec::new();
+        let mut buffer = Vec::new();
+
+        reader.write_all(b"VV\n").await?;
+        reader.flush().await?;
+        // Frames of a stream left running by an earlier session may come first.
+        let mut has_stale_stream = false;
+        loop {
+            recv_response(&mut reader, &mut raw).await?;
+            if raw.starts_with(b"VV\n") {
+                break;
+            }
+            has_stale_stream = true;
+        }
+        let negotiated_protocol = if is_scip1_response(&mut &raw[3..], &mut buffer)? {
+            reader.write_all(b"SCIP2.0\n").await?;
+            reader.flush().await?;
+            recv_response(&mut reader, &mut raw).await?;
+            check_scip2_switch_response(&mut raw.as_slice(), &mut buffer)?;
+            ScipProtocol::Scip1SwitchedToScip2
+        } else {
+            ScipProtocol::Scip2
+        };
+
+        let urg = Self {
+            stream: Arc::new(AsyncMutex::new(reader)),
+            frames: Arc::new(Mutex::new(FrameQueue::default())),
+            time_stamp_tracker: Arc::new(Mutex::new(TimeStampTracker::new())),
+            negotiated_protocol,
+            is_capturing: Arc::new(AtomicBool::new(false)),
+            capabilities: Mutex::new(None),
+            interface,
+        };
+
+        if has_stale_stream && negotiated_protocol == ScipProtocol::Scip2 {
+            send_qt(&urg.stream, &urg.frames, &urg.is_capturing).await?;
+        }
+        urg.get_version_info().await?;
+        Ok(urg)
+    }
+
+    pub async fn get_version_info(&self) -> Result<UrgVersionInfo, UrgError> {
+        let mut reader = self.stream.lock().await;
+        let (mut raw, mut buffer) = (Vec::new(), Vec::new());
+
+        let (_, mut res) = self
+            .send_cmd(&mut reader, &mut raw, &mut buffer, "VV", "00")
+            .await?;
+        let fields = recv_fields(&mut res, &mut buffer, "VV")?;
+        UrgVersionInfo::from_fields(fields, self.negotiated_protocol)
+    }
+
+    pub async fn get_sensor_params(&self) -> Result<UrgSensorParams, UrgError> {
+        let mut reader = self.stream.lock().await;
+        let (mut raw, mut buffer) = (Vec::new(), Vec::new());
+
+        let (_, mut res) = self
+            .send_cmd(&mut reader, &mut raw, &mut buffer, "PP", "00")
+            .await?;
+        let fields = recv_fields(&mut res, &mut buffer, "PP")?;
+        UrgSensorParams::from_fields(fields)
+    }
+
+    pub async fn get_status_info(&self) -> Result<UrgStatusInfo, UrgError> {
+        let mut reader = self.stream.lock().await;
+        let (mut raw, mut buffer) = (Vec::new(), Vec::new());
+
+        let (_, mut res) = self
+            .send_cmd(&mut reader, &mut raw, &mut buffer, "II", "00")
+            .await?;
+        let fields = recv_fields(&mut res, &mut buffer, "II")?;
+        UrgStatusInfo::from_fields(fields)
+    }
+
+    /// Like `Urg::get_capabilities`.
+    pub async fn get_capabilities(&self) -> Result<UrgCapabilities, UrgError> {
+        if let Some(capabilities) = UrgCapabilities::cached(&self.capabilities) {
+            return Ok(capabilities);
+        }
+
+        let version = self.get_version_info().await?;
+        let params = self.get_sensor_params().await?;
+        let capabilities = match UrgCapabilities::known(&version, &params, self.interface) {
+            Some(capabilities) => capabilities,
+            None => {
+                let [ge, hd, gs] = UrgCapabilities::probe_cmds(&params);
+                let supported = (
+                    self.probe(&ge).await?,
+                    self.probe(&hd).await?,
+                    self.probe(&gs).await?,
+                );
+                UrgCapabilities::new(&params, supported, self.interface)
+            }
+        };
+        Ok(capabilities.store(&self.capabilities))
+    }
+
+    async fn probe(&self, cmd: &str) -> Result<bool, UrgError> {
+        let mut reader = self.stream.lock().await;
+        let (mut raw, mut buffer) = (Vec::new(), Vec::new());
+        let res = self
+            .send_cmd(&mut reader, &mut raw, &mut buffer, cmd, "00")
+            .await;
+        UrgCapabilities::probe_supported(res)
+    }
+
+    async fn check_supported(&self, cmd: &str) -> Result<(), UrgError> {
+        self.get_capabilities().await?.check(cmd)
+    }
+
+    /// Whether the laser was turned on with `start_capture` and not turned off since.
+    pub fn is_capturing(&self) -> bool {
+        self.is_capturing.load(Ordering::Relaxed)
+    }
+
+    pub async fn start_capture(&mut self) -> Result<(), UrgError> {
+        let mut reader = self.stream.lock().await;
+        let (mut raw, mut buffer) = (Vec::new(), Vec::new());
+
+        self.send_cmd(&mut reader, &mut raw, &mut buffer, "BM", "00")
+            .await?;
+        self.is_capturing.store(true, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    pub async fn stop_capture(&mut self) -> Result<(), UrgError> {
+        send_qt(&self.stream, &self.frames, &self.is_capturing).await
+    }
+
+    pub async fn get_distance(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+    ) -> Result<UrgPayload, UrgError> {
+        let mut payload = UrgPayload::default();
+        self.get_distance_into(start_step, end_step, cluster_count, &mut payload)
+            .await?;
+        Ok(payload)
+    }
+
+    /// Like `get_distance`, but decodes into `payload`, reusing its vectors.
+    pub async fn get_distance_into(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+        payload: &mut UrgPayload,
+    ) -> Result<(), UrgError> {
+        let cmd = format!("GD{:0>4}{:0>4}{:0>2}", start_step, end_step, cluster_count);
+        self.get_scan_into(&cmd, 3, false, payload).await
+    }
+
+    pub async fn get_distance_multi(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+        scan_skip_count: u32,
+        num_of_scan: u32,
+    ) -> Result<UrgPayloadStream<T>, UrgError> {
+        let cmd = format!(
+            "MD{:0>4}{:0>4}{:0>2}{:0>1}{:0>2}",
+            start_step, end_step, cluster_count, scan_skip_count, num_of_scan
+        );
+
+        Ok(UrgPayloadStream {
+            frames: Frames::new(self.start_stream(cmd, num_of_scan, 3, false).await?),
+        })
+    }
+
+    pub async fn get_distance_short(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+    ) -> Result<UrgPayload, UrgError> {
+        let mut payload = UrgPayload::default();
+        self.get_distance_short_into(start_step, end_step, cluster_count, &mut payload)
+            .await?;
+        Ok(payload)
+    }
+
+    /// Like `get_distance_short`, but decodes into `payload`, reusing its vectors.
+    pub async fn get_distance_short_into(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+        payload: &mut UrgPayload,
+    ) -> Result<(), UrgError> {
+        let cmd = format!("GS{:0>4}{:0>4}{:0>2}", start_step, end_step, cluster_count);
+        self.get_scan_into(&cmd, 2, false, payload).await
+    }
+
+    pub async fn get_distance_short_multi(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+        scan_skip_count: u32,
+        num_of_scan: u32,
+    ) -> Result<UrgPayloadStream<T>, UrgError> {
+        let cmd = format!(
+            "MS{:0>4}{:0>4}{:0>2}{:0>1}{:0>2}",
+            start_step, end_step, cluster_count, scan_skip_count, num_of_scan
+        );
+
+        Ok(UrgPayloadStream {
+            frames: Frames::new(self.start_stream(cmd, num_of_scan, 2, false).await?),
+        })
+    }
+
+    pub async fn get_distance_intensity(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+    ) -> Result<UrgPayload, UrgError> {
+        let mut payload = UrgPayload::default();
+        self.get_distance_intensity_into(start_step, end_step, cluster_count, &mut payload)
+            .await?;
+        Ok(payload)
+    }
+
+    /// Like `get_distance_intensity`, but decodes into `payload`, reusing its vectors.
+    pub async fn get_distance_intensity_into(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+        payload: &mut UrgPayload,
+    ) -> Result<(), UrgError> {
+        let cmd = format!("GE{:0>4}{:0>4}{:0>2}", start_step, end_step, cluster_count);
+        self.get_scan_into(&cmd, 3, true, payload).await
+    }
+
+    pub async fn get_distance_intensity_multi(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+        scan_skip_count: u32,
+        num_of_scan: u32,
+    ) -> Result<UrgPayloadStream<T>, UrgError> {
+        let cmd = format!(
+            "ME{:0>4}{:0>4}{:0>2}{:0>1}{:0>2}",
+            start_step, end_step, cluster_count, scan_skip_count, num_of_scan
+        );
+
+        Ok(UrgPayloadStream {
+            frames: Frames::new(self.start_stream(cmd, num_of_scan, 3, true).await?),
+        })
+    }
+
+    pub async fn get_multi_echo_distance(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+    ) -> Result<UrgMultiEchoPayload, UrgError> {
+        let cmd = format!("HD{:0>4}{:0>4}{:0>2}", start_step, end_step, cluster_count);
+        self.get_multi_echo_scan(&cmd, false).await
+    }
+
+    pub async fn get_multi_echo_distance_multi(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+        scan_skip_count: u32,
+        num_of_scan: u32,
+    ) -> Result<UrgMultiEchoPayloadStream<T>, UrgError> {
+        let cmd = format!(
+            "ND{:0>4}{:0>4}{:0>2}{:0>1}{:0>2}",
+            start_step, end_step, cluster_count, scan_skip_count, num_of_scan
+        );
+
+        Ok(UrgMultiEchoPayloadStream {
+            frames: Frames::new(self.start_stream(cmd, num_of_scan, 3, false).await?),
+        })
+    }
+
+    pub async fn get_multi_echo_distance_intensity(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+    ) -> Result<UrgMultiEchoPayload, UrgError> {
+        let cmd = format!("HE{:0>4}{:0>4}{:0>2}", start_step, end_step, cluster_count);
+        self.get_multi_echo_scan(&cmd, true).await
+    }
+
+    pub async fn get_multi_echo_distance_intensity_multi(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+        scan_skip_count: u32,
+        num_of_scan: u32,
+    ) -> Result<UrgMultiEchoPayloadStream<T>, UrgError> {
+        let cmd = format!(
+            "NE{:0>4}{:0>4}{:0>2}{:0>1}{:0>2}",
+            start_step, end_step, cluster_count, scan_skip_count, num_of_scan
+        );
+
+        Ok(UrgMultiEchoPayloadStream {
+            frames: Frames::new(self.start_stream(cmd, num_of_scan, 3, true).await?),
+        })
+    }
+
+    async fn get_scan_into(
+        &self,
+        cmd: &str,
+        encoding_len: usize,
+        has_intensity: bool,
+        payload: &mut UrgPayload,
+    ) -> Result<(), UrgError> {
+        self.check_supported(cmd).await?;
+        let mut reader = self.stream.lock().await;
+        let (mut raw, mut buffer) = (Vec::new(), Vec::new());
+
+        let (_, mut res) = self
+            .send_cmd(&mut reader, &mut raw, &mut buffer, cmd, "00")
+            .await?;
+        recv_scan_into(
+            &mut res,
+            &mut buffer,
+            cmd,
+            encoding_len,
+            has_intensity,
+            payload,
+        )?;
+        payload.extended_time_stamp = lock(&self.time_stamp_tracker).update(payload.time_stamp);
+        Ok(())
+    }
+
+    async fn get_multi_echo_scan(
+        &self,
+        cmd: &str,
+        has_intensity: bool,
+    ) -> Result<UrgMultiEchoPayload, UrgError> {
+        self.check_supported(cmd).await?;
+        let mut reader = self.stream.lock().await;
+        let (mut raw, mut buffer) = (Vec::new(), Vec::new());
+
+        let (_, mut res) = self
+            .send_cmd(&mut reader, &mut raw, &mut buffer, cmd, "00")
+            .await?;
+        let (time_stamp, raw_data) = get_raw_data(&mut res, &mut buffer, cmd)?;
+        let extended_time_stamp = lock(&self.time_stamp_tracker).update(time_stamp);
+        let (distance, intensity) = decode_multi_echo(&raw_data, has_intensity);
+
+        Ok(UrgMultiEchoPayload {
+            time_stamp,
+            extended_time_stamp,
+            distance,
+            intensity,
+        })
+    }
+
+    async fn start_stream(
+        &self,
+        cmd: String,
+        num_of_scan: u32,
+        encoding_len: usize,
+        has_intensity: bool,
+    ) -> Result<ScanState<T>, UrgError> {
+        self.check_supported(&cmd).await?;
+        let mut reader = self.stream.lock().await;
+        let (mut raw, mut buffer) = (Vec::new(), Vec::new());
+
+        let (tag, _) = self
+            .send_cmd(&mut reader, &mut raw, &mut buffer, &cmd, "00")
+            .await?;
+        // Registered while the connection is still locked, so that other commands queue its
+        // frames instead of dropping them as stale.
+        lock(&self.frames).add_stream(tag.clone(), num_of_scan != 0);
+
+        Ok(ScanState {
+            stream: self.stream.clone(),
+            frames: self.frames.clone(),
+            time_stamp_tracker: self.time_stamp_tracker.clone(),
+            is_capturing: self.is_capturing.clone(),
+            count: if num_of_scan == 0 {
+                None
+            } else {
+                Some(num_of_scan)
+            },
+            cmd,
+            tag,
+            encoding_len,
+            has_intensity,
+            raw,
+            buffer,
+        })
+    }
+
+    // Sends `cmd` with a fresh tag and reads its whole response into `raw`. Returns the tag and
+    // the data lines that follow the checked echo and status.
+    async fn send_cmd<'a>(
+        &self,
+        reader: &mut BufReader<T>,
+        raw: &'a mut Vec<u8>,
+        buffer: &mut Vec<u8>,
+        cmd: &str,
+        ok_status: &str,
+    ) -> Result<(String, &'a [u8]), UrgError> {
+        let tag = write_tagged_cmd(reader, cmd).await?;
+        recv_tagged_response(reader, raw, &tag, &self.frames).await?;
+        let mut res = raw.as_slice();
+        check_send_cmd_response(&mut res, buffer, cmd, Some(&tag), None, ok_status)?;
+        Ok((tag, res))
+    }
+}
+
+struct ScanState<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> {
+    stream: Arc<AsyncMutex<BufReader<T>>>,
+    frames: Arc<Mutex<FrameQueue>>,
+    time_stamp_tracker: Arc<Mutex<TimeStampTracker>>,
+    is_capturing: Arc<AtomicBool>,
+    count: Option<u32>,
+    cmd: String,
+    tag: String,
+    encoding_len: usize,
+    has_intensity: bool,
+    raw: Vec<u8>,
+    buffer: Vec<u8>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> ScanState<T> {
+    // Reads the next frame, queued or not, and hands its data lines to `read_data`, which
+    // returns the time stamp and the decoded data.
+    async fn next_frame<D>(
+        &mut self,
+        read_data: impl FnOnce(&mut &[u8], &mut Vec<u8>, &str) -> Result<(u32, D), UrgError>,
+    ) -> Option<Result<(u32, u64, D), UrgError>> {
+        let frame_cmd = match self.count {
+            Some(0) => return None,
+            Some(count) => format!("{}{:0>2}", &self.cmd[..self.cmd.len() - 2], count - 1),
+            None => self.cmd.clone(),
+        };
+        let res = self.read_frame(&frame_cmd, read_data).await;
+
+        Some(res.map(|(time_stamp, data)| {
+            let extended_time_stamp = lock(&self.time_stamp_tracker).update(time_stamp);
+            (time_stamp, extended_time_stamp, data)
+        }))
+    }
+
+    async fn read_frame<D>(
+        &mut self,
+        frame_cmd: &str,
+        read_data: impl FnOnce(&mut &[u8], &mut Vec<u8>, &str) -> Result<(u32, D), UrgError>,
+    ) -> Result<(u32, D), UrgError> {
+        let queued = lock(&self.frames).pop(&self.tag);
+        match queued {
+            Some(raw) => self.raw = raw,
+            None => {
+                let mut reader = self.stream.lock().await;
+                recv_tagged_response(&mut *reader, &mut self.raw, &self.tag, &self.frames).await?;
+            }
+        }
+        let mut res = self.raw.as_slice();
+        check_send_cmd_response(
+            &mut res,
+            &mut self.buffer,
+            frame_cmd,
+            Some(&self.tag),
+            None,
+            "99",
+        )?;
+        if let Some(count) = self.count {
+            self.count = Some(count - 1);
+        }
+        read_data(&mut res, &mut self.buffer, frame_cmd)
+    }
+
+    async fn next_payload(mut self) -> (Self, Option<Result<UrgPayload, UrgError>>) {
+        let (encoding_len, has_intensity) = (self.encoding_len, self.has_intensity);
+        let mut payload = UrgPayload::default();
+        let res = self
+            .next_frame(|reader, buffer, cmd| {
+                recv_scan_into(
+                    reader,
+                    buffer,
+                    cmd,
+                    encoding_len,
+                    has_intensity,
+                    &mut payload,
+                )
+                .map(|()| (payload.time_stamp, ()))
+            })
+            .await;
+        let res = res.map(|res| {
+            res.map(|(_, extended_time_stamp, ())| {
+                payload.extended_time_stamp = extended_time_stamp;
+                payload
+            })
+        });
+        (self, res)
+    }
+
+    async fn next_multi_echo_payload(
+        mut self,
+    ) -> (Self, Option<Result<UrgMultiEchoPayload, UrgError>>) {
+        let has_intensity = self.has_intensity;
+        let res = self
+            .next_frame(|reader, buffer, cmd| get_raw_data(reader, buffer, cmd))
+            .await;
+        let res = res.map(|res| {
+            res.map(|(time_stamp, extended_time_stamp, raw_data)| {
+                let (distance, intensity) = decode_multi_echo(&raw_data, has_intensity);
+                UrgMultiEchoPayload {
+                    time_stamp,
+                    extended_time_stamp,
+                    distance,
+                    intensity,
+                }
+            })
+        });
+        (self, res)
+    }
+
+    // Stops a stream that has frames left with `QT`.
+    async fn stop(&mut self) -> Result<(), UrgError> {
+        lock(&self.frames).remove_stream(&self.tag);
+        if self.count == Some(0) {
+            return Ok(());
+        }
+        self.count = Some(0);
+        send_qt(&self.stream, &self.frames, &self.is_capturing).await
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Drop for ScanState<T> {
+    // `QT` cannot be awaited here, so it is sent from a task. Outside of a runtime the stream is
+    // left running.
+    fn drop(&mut self) {
+        lock(&self.frames).remove_stream(&self.tag);
+        if self.count == Some(0) {
+            return;
+        }
+        if let Ok(runtime) = Handle::try_current() {
+            let stream = self.stream.clone();
+            let frames = self.frames.clone();
+            let is_capturing = self.is_capturing.clone();
+            runtime.spawn(async move {
+                _ = send_qt(&stream, &frames, &is_capturing).await;
+            });
+        }
+    }
+}
+
+type NextFrame<T, D> =
+    Pin<Box<dyn Future<Output = (ScanState<T>, Option<Result<D, UrgError>>)> + Send>>;
+
+// Reads one frame at a time, owning the state while a read is pending.
+struct Frames<T: AsyncRead + AsyncWrite + Unpin + Send + 'static, D> {
+    state: Option<ScanState<T>>,
+    next: Option<NextFrame<T, D>>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static, D> Frames<T, D> {
+    fn new(state: ScanState<T>) -> Self {
+        Self {
+            state: Some(state),
+            next: None,
+        }
+    }
+
+    fn poll_next(
+        &mut self,
+        cx: &mut Context<'_>,
+        read: fn(ScanState<T>) -> NextFrame<T, D>,
+    ) -> Poll<Option<Result<D, UrgError>>> {
+        if self.next.is_none() {
+            let Some(state) = self.state.take() else {
+                return Poll::Ready(None);
+            };
+            self.next = Some(read(state));
+        }
+        let next = self.next.as_mut().unwrap();
+        let (state, res) = ready!(next.as_mut().poll(cx));
+        self.next = None;
+        self.state = Some(state);
+        Poll::Ready(res)
+    }
+
+    // A read still pending is dropped, which leaves `QT` to the drop of its state.
+    async fn cancel(&mut self) -> Result<(), UrgError> {
+        self.next = None;
+        match self.state.as_mut() {
+            Some(state) => state.stop().await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Scans of a `MD`/`MS`/`ME` stream. Dropping it stops the stream with `QT`, sent from a task on
+/// the current runtime.
+pub struct UrgPayloadStream<T: AsyncRead + AsyncWrite + Unpin + Send + 'static = TcpStream> {
+    frames: Frames<T, UrgPayload>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> UrgPayloadStream<T> {
+    /// Stops the stream with `QT`, which also turns the laser off.
+    pub async fn cancel(mut self) -> Result<(), UrgError> {
+        self.frames.cancel().await
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Stream for UrgPayloadStream<T> {
+    type Item = Result<UrgPayload, UrgError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut()
+            .frames
+            .poll_next(cx, |state| Box::pin(state.next_payload()))
+    }
+}
+
+/// Scans of a `ND`/`NE` stream. Dropping it stops the stream with `QT`, sent from a task on the
+/// current runtime.
+pub struct UrgMultiEchoPayloadStream<T: AsyncRead + AsyncWrite + Unpin + Send + 'static = TcpStream>
+{
+    frames: Frames<T, UrgMultiEchoPayload>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> UrgMultiEchoPayloadStream<T> {
+    /// Stops the stream with `QT`, which also turns the laser off.
+    pub async fn cancel(mut self) -> Result<(), UrgError> {
+        self.frames.cancel().await
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Stream for UrgMultiEchoPayloadStream<T> {
+    type Item = Result<UrgMultiEchoPayload, UrgError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut()
+            .frames
+            .poll_next(cx, |state| Box::pin(state.next_multi_echo_payload()))
+    }
+}
+
+async fn write_tagged_cmd(
+    writer: &mut (impl AsyncWrite + Unpin),
+    cmd: &str,
+) -> Result<String, UrgError> {
+    let tag = next_tag();
+    writer
+        .write_all(format!("{cmd};{tag}\n").as_bytes())
+        .await?;
+    writer.flush().await?;
+    Ok(tag)
+}
+
+// Sends `QT`, skipping the frames of streams that arrive before its response.
+async fn send_qt<T: AsyncRead + AsyncWrite + Unpin>(
+    stream: &AsyncMutex<BufReader<T>>,
+    frames: &Mutex<FrameQueue>,
+    is_capturing: &AtomicBool,
+) -> Result<(), UrgError> {
+    let mut reader = stream.lock().await;
+    let (mut raw, mut buffer) = (Vec::new(), Vec::new());
+
+    let tag = write_tagged_cmd(&mut *reader, "QT").await?;
+    recv_tagged_response(&mut *reader, &mut raw, &tag, frames).await?;
+    check_send_cmd_response(
+        &mut raw.as_slice(),
+        &mut buffer,
+        "QT",
+        Some(&tag),
+        None,
+        "00",
+    )?;
+    // `QT` also turns the laser off.
+    is_capturing.store(false, Ordering::Relaxed);
+    Ok(())
+}
+
+// Reads one whole response, up to the blank line that ends it, into `raw`.
+async fn recv_response(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    raw: &mut Vec<u8>,
+) -> Result<(), UrgError> {
+    raw.clear();
+    loop {
+        let n = reader.read_until(b'\n', raw).await?;
+        if n == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        if raw.ends_with(b"\n\n") || raw == b"\n" {
+            return Ok(());
+        }
+    }
+}
+
+// Reads responses until the one echoing `tag`. Frames of running streams are queued for them;
+// anything else is stale and dropped.
+async fn recv_tagged_response(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    raw: &mut Vec<u8>,
+    tag: &str,
+    frames: &Mutex<FrameQueue>,
+) -> Result<(), UrgError> {
+    loop {
+        recv_response(reader, raw).await?;
+        let echo = ByteSlice::lines(raw.as_slice()).next().unwrap_or_default();
+        match echo.rsplit_once_str(";") {
+            Some((_, echo_tag)) if echo_tag == tag.as_bytes() => return Ok(()),
+            Some((_, echo_tag)) => {
+                let echo_tag = echo_tag.to_str_lossy().into_owned();
+                lock(frames).push(echo_tag, raw.clone());
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{
+    lock, recv_data, ScipStatus, Transport, Urg, UrgError, UrgSensorParams, UrgVersionInfo,
+};
+use bstr::{BString, ByteSlice};
+use std::{sync::Mutex, time::Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterfaceType {
+    Ethernet,
+    Serial,
+    Other,
+}
+
+/// What a sensor model can do, derived from `VV`/`PP` and probing unknown models.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UrgCapabilities {
+    pub sensor_model: BString,
+    /// `GE`/`ME`
+    pub intensity: bool,
+    /// `HD`/`ND`, and `HE`/`NE` when `intensity` is also supported.
+    pub multi_echo: bool,
+    /// The 2-character encoding of `GS`/`MS`. The 3-character encoding is always available.
+    pub short_encoding: bool,
+    pub min_step: u32,
+    pub max_step: u32,
+    pub scan_speed_rpm: u32,
+    pub interface: InterfaceType,
+}
+
+impl UrgCapabilities {
+    pub fn scan_period(&self) -> Duration {
+        crate::scan_period(self.scan_speed_rpm)
+    }
+
+    fn supports(&self, cmd: &str) -> bool {
+        match cmd.get(..2) {
+            Some("GE" | "ME") => self.intensity,
+            Some("HD" | "ND") => self.multi_echo,
+            Some("HE" | "NE") => self.multi_echo && self.intensity,
+            Some("GS" | "MS") => self.short_encoding,
+            _ => true,
+        }
+    }
+
+    // Refuses a scan command the model does not support or whose steps it does not have.
+    pub(crate) fn check(&self, cmd: &str) -> Result<(), UrgError> {
+        if !self.supports(cmd) {
+            return Err(UrgError::Unsupported {
+                cmd: cmd.to_string(),
+                sensor_model: self.sensor_model.clone(),
+            });
+        }
+        let step = |range| {
+            cmd.get(range)
+                .and_then(|step: &str| step.parse::<u32>().ok())
+        };
+        if let (Some(start_step), Some(end_step)) = (step(2..6), step(6..10)) {
+            if start_step < self.min_step || end_step > self.max_step || start_step > end_step {
+                return Err(UrgError::InvalidArgument(format!(
+                    "steps {start_step} to {end_step} are outside {} to {} of {}",
+                    self.min_step, self.max_step, self.sensor_model
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // From the built-in table, or `None` for a model that has to be probed.
+    pub(crate) fn known(
+        version: &UrgVersionInfo,
+        params: &UrgSensorParams,
+        interface: InterfaceType,
+    ) -> Option<Self> {
+        let &(_, intensity, multi_echo, short_encoding) =
+            KNOWN_MODELS.iter().find(|(prefix, ..)| {
+                params.sensor_model.starts_with_str(prefix)
+                    || version.product_info.contains_str(prefix)
+            })?;
+        Some(Self::new(
+            params,
+            (intensity, multi_echo, short_encoding),
+            interface,
+        ))
+    }
+
+    // Scans of the front step with `GE`, `HD` and `GS`, which probe a model not in the table.
+    pub(crate) fn probe_cmds(params: &UrgSensorParams) -> [String; 3] {
+        let step = params.front_dir_step;
+        ["GE", "HD", "GS"].map(|cmd| format!("{cmd}{step:0>4}{step:0>4}00"))
+    }
+
+    // Any answer to a probe command but "undefined command" means the model knows the command,
+    // even if it cannot run it right now (e.g. with the laser off).
+    pub(crate) fn probe_supported<R>(res: Result<R, UrgError>) -> Result<bool, UrgError> {
+        match res {
+            Ok(_) => Ok(true),
+            Err(UrgError::Status {
+                status: ScipStatus::UndefinedCommand,
+                ..
+            }) => Ok(false),
+            Err(UrgError::Status { .. }) => Ok(true),
+            Err(err) => Err(err),
+        }
+    }
+
+    // The capabilities `Urg` and `AsyncUrg` cache for their connection after the first query.
+    pub(crate) fn cached(cache: &Mutex<Option<Self>>) -> Option<Self> {
+        lock(cache).clone()
+    }
+
+    pub(crate) fn store(self, cache: &Mutex<Option<Self>>) -> Self {
+        *lock(cache) = Some(self.clone());
+        self
+    }
+
+    pub(crate) fn new(
+        params: &UrgSensorParams,
+        (intensity, multi_echo, short_encoding): (bool, bool, bool),
+        interface: InterfaceType,
+    ) -> Self {
+        Self {
+            sensor_model: params.sensor_model.clone(),
+            intensity,
+            multi_echo,
+            short_encoding,
+            min_step: params.start_step,
+            max_step: params.end_step,
+            scan_speed_rpm: params.std_scan_speed_rpm,
+            interface,
+        }
+    }
+}
+
+// (model prefix, intensity, multi echo, short encoding), more specific prefixes first.
+const KNOWN_MODELS: [(&str, bool, bool, bool); 7] = [
+    ("URG-04LX", false, false, true),
+    ("UBG-04LX", false, false, true),
+    ("UHG-08LX", false, false, true),
+    ("UTM-30LX-EW", true, true, true),
+    ("UTM-30LX", true, false, true),
+    ("UXM-30LX", true, true, true),
+    ("UST-", true, false, true),
+];
+
+impl<T: Transport> Urg<T> {
+    /// Capabilities of the connected model. Models not in the built-in table are probed with
+    /// `GE`, `HD` and `GS` once and the result is cached.
+    pub fn get_capabilities(&self) -> Result<UrgCapabilities, UrgError> {
+        if let Some(capabilities) = UrgCapabilities::cached(&self.capabilities) {
+            return Ok(capabilities);
+        }
+
+        let version = self.get_version_info()?;
+        let params = self.get_sensor_params()?;
+        let interface = lock(&self.stream).get_ref().interface_type();
+        let capabilities = match UrgCapabilities::known(&version, &params, interface) {
+            Some(capabilities) => capabilities,
+            None => {
+                let [ge, hd, gs] = UrgCapabilities::probe_cmds(&params);
+                let supported = (self.probe(&ge)?, self.probe(&hd)?, self.probe(&gs)?);
+                UrgCapabilities::new(&params, supported, interface)
+            }
+        };
+        Ok(capabilities.store(&self.capabilities))
+    }
+
+    fn probe(&self, cmd: &str) -> Result<bool, UrgError> {
+        self.with_reader(|reader, buffer| {
+            let res = self.send_cmd(reader, buffer, cmd, "00");
+            if res.is_ok() {
+                while recv_data(reader, buffer, cmd)? > 1 {}
+            }
+            UrgCapabilities::probe_supported(res)
+        })
+    }
+
+    pub(crate) fn check_supported(&self, cmd: &str) -> Result<(), UrgError> {
+        self.get_capabilities()?.check(cmd)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{InterfaceType, UrgCapabilities};
+    use crate::{ScipStatus, UrgError};
+
+    #[test]
+    fn supports_test() {
+        let capabilities = UrgCapabilities {
+            sensor_model: "URG-04LX".into(),
+            intensity: false,
+            multi_echo: false,
+            short_encoding: true,
+            min_step: 44,
+            max_step: 725,
+            scan_speed_rpm: 600,
+            interface: InterfaceType::Serial,
+        };
+        assert!(capabilities.supports("GD0044072500"));
+        assert!(capabilities.supports("MS0044072500000"));
+        assert!(!capabilities.supports("GE0044072500"));
+        assert!(!capabilities.supports("NE0044072500000"));
+        assert!(capabilities.check("MD0044072500000").is_ok());
+        assert!(matches!(
+            capabilities.check("GD0000072500"),
+            Err(UrgError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            capabilities.check("GS0044080000"),
+            Err(UrgError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            capabilities.check("GE0044072500"),
+            Err(UrgError::Unsupported { .. })
+        ));
+    }
+
+    #[test]
+    fn probe_supported_test() {
+        let status = |raw: &str| UrgError::Status {
+            cmd: "GE0384038400".to_string(),
+            status: ScipStatus::new("GE0384038400", raw.as_bytes()),
+            raw: raw.into(),
+        };
+        assert!(UrgCapabilities::probe_supported(Ok(())).unwrap());
+        assert!(!UrgCapabilities::probe_supported::<()>(Err(status("0E"))).unwrap());
+        // The laser is off, but the command is known.
+        assert!(UrgCapabilities::probe_supported::<()>(Err(status("10"))).unwrap());
+        assert!(matches!(
+            UrgCapabilities::probe_supported::<()>(Err(UrgError::Timeout {
+                cmd: "GE0384038400".to_string()
+            })),
+            Err(UrgError::Timeout { .. })
+        ));
+    }
+}
@@ -1,19 +1,498 @@
+#[cfg(feature = "tokio")]
+mod async_urg;
+mod capabilities;
+mod reconnect;
+mod scan_stream;
+#[cfg(unix)]
+mod serial;
+mod time_sync;
+
+#[cfg(feature = "tokio")]
+pub use async_urg::{AsyncUrg, UrgMultiEchoPayloadStream, UrgPayloadStream};
+pub use capabilities::{InterfaceType, UrgCapabilities};
+pub use reconnect::{ReconnectEvent, ReconnectPolicy};
+pub use scan_stream::{OverflowPolicy, ScanStream};
+#[cfg(unix)]
+pub use serial::{SerialPort, SERIAL_BAUD_RATES};
+pub use time_sync::{TimeStampTracker, UrgTimeSync};
+
 use bstr::{BString, ByteSlice};
+use reconnect::{reconnect, ReconnectState};
 use std::{
-    io::{self, BufRead, BufReader, BufWriter, Write},
-    net::{IpAddr, TcpStream},
-    sync::Arc,
+    collections::{BTreeMap, VecDeque},
+    error::Error,
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex, MutexGuard, OnceLock, PoisonError,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+/// A byte stream speaking SCIP 2.0, such as a TCP connection or a serial port.
+///
+/// Implement it for any other `Read + Write` type (e.g. an in-memory pipe) to run `Urg` over it.
+pub trait Transport: Read + Write {
+    fn interface_type(&self) -> InterfaceType {
+        InterfaceType::Other
+    }
+
+    /// How long a read waits for data; `None` waits forever. A transport without timeouts ignores
+    /// it.
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for TcpStream {
+    fn interface_type(&self) -> InterfaceType {
+        InterfaceType::Ethernet
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Transport for std::os::unix::net::UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_write_timeout(self, timeout)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn interface_type(&self) -> InterfaceType {
+        (**self).interface_type()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_write_timeout(timeout)
+    }
+}
+
+// SCIP 2.0 echoes up to 16 characters appended to a command after `;`.
+const MAX_TAG_LEN: usize = 16;
+
+#[derive(Debug)]
+pub enum UrgError {
+    Io(io::Error),
+    EchoMismatch {
+        cmd: String,
+        received: BString,
+    },
+    Status {
+        cmd: String,
+        status: ScipStatus,
+        raw: BString,
+    },
+    Checksum {
+        cmd: String,
+        line_index: usize,
+        expected: u8,
+        actual: u8,
+        line: BString,
+    },
+    InvalidTimeStamp {
+        cmd: String,
+        line: BString,
+    },
+    MalformedLine {
+        cmd: String,
+        line_index: usize,
+        line: BString,
+    },
+    MissingField {
+        cmd: String,
+        key: String,
+    },
+    InvalidField {
+        cmd: String,
+        key: String,
+        value: BString,
+    },
+    /// The connected model does not support `cmd`; nothing was sent.
+    Unsupported {
+        cmd: String,
+        sensor_model: BString,
+    },
+    InvalidArgument(String),
+    /// No response to `cmd` within the read timeout. For a stream, `cmd` is the echo of the frame
+    /// that was expected.
+    Timeout {
+        cmd: String,
+    },
+}
+
+impl fmt::Display for UrgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrgError::Io(err) => write!(f, "io error: {err}"),
+            UrgError::EchoMismatch { cmd, received } => {
+                write!(f, "echo mismatch for {cmd}: recv {received}")
+            }
+            UrgError::Status { cmd, status, raw } => {
+                write!(f, "{cmd} failed: {status} (status {raw})")
+            }
+            UrgError::Checksum {
+                cmd,
+                line_index,
+                expected,
+                actual,
+                line,
+            } => write!(
+                f,
+                "checksum mismatch in response to {} at line {}: expected {:?} but got {:?}. line: {}",
+                cmd, line_index, *expected as char, *actual as char, line
+            ),
+            UrgError::InvalidTimeStamp { cmd, line } => {
+                write!(f, "invalid timestamp in response to {cmd}: {line}")
+            }
+            UrgError::MalformedLine {
+                cmd,
+                line_index,
+                line,
+            } => write!(
+                f,
+                "malformed line in response to {cmd} at line {line_index}: {line}"
+            ),
+            UrgError::MissingField { cmd, key } => {
+                write!(f, "response to {cmd} has no {key} field")
+            }
+            UrgError::InvalidField { cmd, key, value } => {
+                write!(f, "invalid {key} field in response to {cmd}: {value}")
+            }
+            UrgError::Unsupported { cmd, sensor_model } => {
+                write!(f, "{cmd} is not supported by {sensor_model}")
+            }
+            UrgError::InvalidArgument(msg) => write!(f, "invalid argument: {msg}"),
+            UrgError::Timeout { cmd } => write!(f, "timed out waiting for the response to {cmd}"),
+        }
+    }
+}
+
+impl Error for UrgError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            UrgError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for UrgError {
+    fn from(err: io::Error) -> Self {
+        UrgError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScipStatus {
+    Ok,
+    StreamingData,
+    RebootPending,
+    InvalidStartStep,
+    InvalidEndStep,
+    InvalidClusterCount,
+    EndStepOutOfRange,
+    EndStepBeforeStartStep,
+    InvalidScanInterval,
+    InvalidNumberOfScans,
+    LaserOff,
+    LaserMalfunction,
+    LaserAlreadyOn,
+    ProcessingStopped(u8),
+    HardwareTrouble(u8),
+    ResumedAfterStop,
+    InvalidParameter,
+    AlreadyInEffect,
+    NotSupportedByModel,
+    TimeAdjustModeOff,
+    ResponseUnavailable,
+    BufferShortage,
+    InsufficientParameters,
+    UndefinedCommand,
+    StringTagTooLong,
+    InvalidStringTag,
+    FirmwareUpdateMode,
+    Unknown(BString),
+}
+
+impl ScipStatus {
+    /// Decodes the two status bytes of the response to `cmd`, as their meaning depends on the command.
+    pub fn new(cmd: &str, status: &[u8]) -> Self {
+        let command = cmd.get(..2).unwrap_or(cmd);
+        let is_scan = matches!(
+            command,
+            "GD" | "GE" | "GS" | "HD" | "HE" | "MD" | "ME" | "MS" | "ND" | "NE"
+        );
+        let is_stream = matches!(command, "MD" | "ME" | "MS" | "ND" | "NE");
+        match status {
+            b"00" => ScipStatus::Ok,
+            b"99" => ScipStatus::StreamingData,
+            b"0A" => ScipStatus::ResponseUnavailable,
+            b"0B" => ScipStatus::BufferShortage,
+            b"0C" | b"0F" => ScipStatus::InsufficientParameters,
+            b"0D" | b"0E" => ScipStatus::UndefinedCommand,
+            b"0G" => ScipStatus::StringTagTooLong,
+            b"0H" => ScipStatus::InvalidStringTag,
+            b"0I" => ScipStatus::FirmwareUpdateMode,
+            b"01" if command == "RB" => ScipStatus::RebootPending,
+            b"01" if command == "BM" => ScipStatus::LaserMalfunction,
+            b"02" if command == "BM" => ScipStatus::LaserAlreadyOn,
+            b"01" if is_scan => ScipStatus::InvalidStartStep,
+            b"02" if is_scan => ScipStatus::InvalidEndStep,
+            b"03" if is_scan => ScipStatus::InvalidClusterCount,
+            b"04" if is_scan => ScipStatus::EndStepOutOfRange,
+            b"05" if is_scan => ScipStatus::EndStepBeforeStartStep,
+            b"06" if is_stream => ScipStatus::InvalidScanInterval,
+            b"07" if is_stream => ScipStatus::InvalidNumberOfScans,
+            b"10" if is_scan => ScipStatus::LaserOff,
+            b"98" if is_stream => ScipStatus::ResumedAfterStop,
+            b"01" | b"02" if matches!(command, "SS" | "CR") => ScipStatus::InvalidParameter,
+            b"03" if matches!(command, "SS" | "CR") => ScipStatus::AlreadyInEffect,
+            b"04" if matches!(command, "SS" | "CR") => ScipStatus::NotSupportedByModel,
+            b"01" if command == "TM" => ScipStatus::InvalidParameter,
+            b"02" | b"03" if command == "TM" => ScipStatus::AlreadyInEffect,
+            b"04" if command == "TM" => ScipStatus::TimeAdjustModeOff,
+            b"01" if command == "HS" => ScipStatus::InvalidParameter,
+            b"02" if command == "HS" => ScipStatus::AlreadyInEffect,
+            b"03" if command == "HS" => ScipStatus::NotSupportedByModel,
+            [high @ b'0'..=b'9', low @ b'0'..=b'9'] => {
+                let code = (high - b'0') * 10 + (low - b'0');
+                match code {
+                    21..=49 if is_stream => ScipStatus::ProcessingStopped(code),
+                    50..=97 => ScipStatus::HardwareTrouble(code),
+                    _ => ScipStatus::Unknown(BString::from(status)),
+                }
+            }
+            _ => ScipStatus::Unknown(BString::from(status)),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        matches!(self, ScipStatus::Ok | ScipStatus::StreamingData)
+    }
+}
+
+impl fmt::Display for ScipStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScipStatus::Ok => write!(f, "ok"),
+            ScipStatus::StreamingData => write!(f, "streaming data"),
+            ScipStatus::RebootPending => write!(f, "waiting for the second reboot command"),
+            ScipStatus::InvalidStartStep => write!(f, "start step is not a decimal number"),
+            ScipStatus::InvalidEndStep => write!(f, "end step is not a decimal number"),
+            ScipStatus::InvalidClusterCount => write!(f, "cluster count is not a decimal number"),
+            ScipStatus::EndStepOutOfRange => write!(f, "end step exceeds sensor range"),
+            ScipStatus::EndStepBeforeStartStep => write!(f, "end step is smaller than start step"),
+            ScipStatus::InvalidScanInterval => write!(f, "scan interval is not a decimal number"),
+            ScipStatus::InvalidNumberOfScans => {
+                write!(f, "number of scans is not a decimal number")
+            }
+            ScipStatus::LaserOff => write!(f, "laser is off"),
+            ScipStatus::LaserMalfunction => {
+                write!(f, "laser can not be switched on due to a malfunction")
+            }
+            ScipStatus::LaserAlreadyOn => write!(f, "laser is already on"),
+            ScipStatus::ProcessingStopped(code) => {
+                write!(f, "processing stopped to verify an error (code {code})")
+            }
+            ScipStatus::HardwareTrouble(code) => write!(f, "hardware trouble (code {code})"),
+            ScipStatus::ResumedAfterStop => {
+                write!(
+                    f,
+                    "measurement resumed after the laser was confirmed normal"
+                )
+            }
+            ScipStatus::InvalidParameter => write!(f, "parameter is invalid"),
+            ScipStatus::AlreadyInEffect => write!(f, "requested setting is already in effect"),
+            ScipStatus::NotSupportedByModel => write!(f, "not supported by this sensor model"),
+            ScipStatus::TimeAdjustModeOff => write!(f, "time adjust mode is off"),
+            ScipStatus::ResponseUnavailable => write!(f, "sensor could not create a response"),
+            ScipStatus::BufferShortage => {
+                write!(f, "buffer shortage or command already being processed")
+            }
+            ScipStatus::InsufficientParameters => write!(f, "command has insufficient parameters"),
+            ScipStatus::UndefinedCommand => write!(f, "command is not defined"),
+            ScipStatus::StringTagTooLong => write!(f, "string tag exceeds 16 characters"),
+            ScipStatus::InvalidStringTag => write!(f, "string tag has invalid characters"),
+            ScipStatus::FirmwareUpdateMode => write!(f, "sensor is in firmware update mode"),
+            ScipStatus::Unknown(status) => write!(f, "unknown status {status}"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct UrgStatusInfo {
     pub sensor_model: BString,
-    pub laser_status: BString,
+    pub laser_status: LaserStatus,
     pub scanning_speed_rpm: u32,
-    pub measurement_mode: BString,
-    pub communication_speed: BString,
+    pub measurement_mode: MeasurementMode,
+    pub communication_speed: CommunicationSpeed,
     pub time_stamp: u32,
-    pub sensor_status: BString,
+    pub sensor_status: SensorStatus,
+    /// Every `KEY:value` pair of the response, including the raw text of the fields above.
+    pub fields: BTreeMap<String, BString>,
+}
+
+impl UrgStatusInfo {
+    pub fn scan_period(&self) -> Duration {
+        scan_period(self.scanning_speed_rpm)
+    }
+
+    fn from_fields(fields: BTreeMap<String, BString>) -> Result<Self, UrgError> {
+        Ok(UrgStatusInfo {
+            sensor_model: get_field(&fields, "II", "MODL")?,
+            laser_status: LaserStatus::new(&get_field(&fields, "II", "LASR")?),
+            scanning_speed_rpm: get_field_u32(&fields, "II", "SCSP")?,
+            measurement_mode: MeasurementMode::new(&get_field(&fields, "II", "MESM")?),
+            communication_speed: CommunicationSpeed::new(&get_field(&fields, "II", "SBPS")?),
+            time_stamp: decode(&get_field(&fields, "II", "TIME")?),
+            sensor_status: SensorStatus::new(&get_field(&fields, "II", "STAT")?),
+            fields,
+        })
+    }
+
+    pub fn is_high_sensitivity(&self) -> bool {
+        self.measurement_mode == MeasurementMode::HighSensitivity
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LaserStatus {
+    On,
+    Off,
+    Unknown(BString),
+}
+
+impl LaserStatus {
+    fn new(value: &[u8]) -> Self {
+        let value_lower = value.to_ascii_lowercase();
+        if value_lower.contains_str("off") {
+            LaserStatus::Off
+        } else if value_lower.contains_str("on") {
+            LaserStatus::On
+        } else {
+            LaserStatus::Unknown(BString::from(value))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MeasurementMode {
+    Normal,
+    HighSensitivity,
+    Idle,
+    Unknown(BString),
+}
+
+impl MeasurementMode {
+    fn new(value: &[u8]) -> Self {
+        let value_lower = value.to_ascii_lowercase();
+        if value_lower.contains_str("high sensitiv") {
+            MeasurementMode::HighSensitivity
+        } else if value_lower.contains_str("normal") {
+            MeasurementMode::Normal
+        } else if value_lower.contains_str("idle") {
+            MeasurementMode::Idle
+        } else {
+            MeasurementMode::Unknown(BString::from(value))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommunicationSpeed {
+    /// Serial or USB link, e.g. `115200[bps]` or `USB Full Speed [12Mbps]`.
+    BitsPerSecond(u32),
+    Ethernet,
+    Unknown(BString),
+}
+
+impl CommunicationSpeed {
+    fn new(value: &[u8]) -> Self {
+        let value_lower = value.to_ascii_lowercase();
+        if value_lower.contains_str("ethernet") {
+            return CommunicationSpeed::Ethernet;
+        }
+        let digits: Vec<u8> = value
+            .iter()
+            .copied()
+            .skip_while(|c| !c.is_ascii_digit())
+            .take_while(u8::is_ascii_digit)
+            .collect();
+        let multiplier = if value_lower.contains_str("mbps") {
+            1_000_000
+        } else if value_lower.contains_str("kbps") {
+            1_000
+        } else {
+            1
+        };
+        digits
+            .to_str()
+            .ok()
+            .and_then(|digits| digits.parse::<u32>().ok())
+            .and_then(|speed| speed.checked_mul(multiplier))
+            .map_or_else(
+                || CommunicationSpeed::Unknown(BString::from(value)),
+                CommunicationSpeed::BitsPerSecond,
+            )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SensorStatus {
+    Normal,
+    /// An abnormal condition with its three digit code, e.g. `Abnormal 051 ...`.
+    Error {
+        code: u32,
+        description: BString,
+    },
+    Unknown(BString),
+}
+
+impl SensorStatus {
+    fn new(value: &[u8]) -> Self {
+        let code = value
+            .split_str(" ")
+            .find(|word| word.len() == 3 && word.iter().all(u8::is_ascii_digit))
+            .map(|word| word.iter().fold(0, |code, c| code * 10 + (c - b'0') as u32));
+        match code {
+            Some(0) => SensorStatus::Normal,
+            Some(code) => SensorStatus::Error {
+                code,
+                description: BString::from(value),
+            },
+            None => {
+                let value_lower = value.to_ascii_lowercase();
+                if value_lower.contains_str("works well") || value_lower.contains_str("normal") {
+                    SensorStatus::Normal
+                } else {
+                    SensorStatus::Unknown(BString::from(value))
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -23,6 +502,33 @@ pub struct UrgVersionInfo {
     pub firmware_version: BString,
     pub protocol_version: BString,
     pub serial_number: BString,
+    pub negotiated_protocol: ScipProtocol,
+    pub fields: BTreeMap<String, BString>,
+}
+
+impl UrgVersionInfo {
+    fn from_fields(
+        fields: BTreeMap<String, BString>,
+        negotiated_protocol: ScipProtocol,
+    ) -> Result<Self, UrgError> {
+        Ok(UrgVersionInfo {
+            vendor_info: get_field(&fields, "VV", "VEND")?,
+            product_info: get_field(&fields, "VV", "PROD")?,
+            firmware_version: get_field(&fields, "VV", "FIRM")?,
+            protocol_version: get_field(&fields, "VV", "PROT")?,
+            serial_number: get_field(&fields, "VV", "SERI")?,
+            negotiated_protocol,
+            fields,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScipProtocol {
+    /// The sensor answered in SCIP 2.0 right away.
+    Scip2,
+    /// The sensor booted in SCIP 1.1 and was switched to SCIP 2.0 with the `SCIP2.0` command.
+    Scip1SwitchedToScip2,
 }
 
 #[derive(Debug)]
@@ -35,287 +541,796 @@ pub struct UrgSensorParams {
     pub end_step: u32,
     pub front_dir_step: u32,
     pub std_scan_speed_rpm: u32,
+    pub scan_direction: ScanDirection,
+    pub fields: BTreeMap<String, BString>,
 }
 
-#[derive(Debug)]
-pub struct UrgPayload {
-    pub time_stamp: u32,
-    pub distance: Vec<u32>,
-    pub intensity: Vec<u32>,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanDirection {
+    CounterClockwise,
+    Clockwise,
+    Unknown(BString),
 }
 
-pub struct UrgPayloadIterator {
-    stream: Arc<TcpStream>,
-    count: Option<u32>,
-    cmd: String,
-    has_intensity: bool,
-    buffer: Vec<u8>,
+impl ScanDirection {
+    fn new(value: &[u8]) -> Self {
+        match value.to_ascii_uppercase().as_slice() {
+            b"CCW" | b"COUNTERCLOCKWISE" | b"COUNTER-CLOCKWISE" => ScanDirection::CounterClockwise,
+            b"CW" | b"CLOCKWISE" => ScanDirection::Clockwise,
+            _ => ScanDirection::Unknown(BString::from(value)),
+        }
+    }
 }
 
-impl Iterator for UrgPayloadIterator {
-    type Item = io::Result<UrgPayload>;
+impl UrgSensorParams {
+    fn from_fields(fields: BTreeMap<String, BString>) -> Result<Self, UrgError> {
+        let angular_area = get_field_u32(&fields, "PP", "ARES")?;
+        // Older firmware does not report the direction; URG sensors scan counterclockwise.
+        let scan_direction = fields
+            .get("SDIR")
+            .map_or(ScanDirection::CounterClockwise, |value| {
+                ScanDirection::new(value)
+            });
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Some(count) = self.count {
-            if count == 0 {
-                return None;
-            }
+        Ok(UrgSensorParams {
+            sensor_model: get_field(&fields, "PP", "MODL")?,
+            min_distance_mm: get_field_u32(&fields, "PP", "DMIN")?,
+            max_distance_mm: get_field_u32(&fields, "PP", "DMAX")?,
+            angular_resolution_deg: 360.0 / angular_area as f32,
+            start_step: get_field_u32(&fields, "PP", "AMIN")?,
+            end_step: get_field_u32(&fields, "PP", "AMAX")?,
+            front_dir_step: get_field_u32(&fields, "PP", "AFRT")?,
+            std_scan_speed_rpm: get_field_u32(&fields, "PP", "SCAN")?,
+            scan_direction,
+            fields,
+        })
+    }
+
+    pub fn std_scan_period(&self) -> Duration {
+        scan_period(self.std_scan_speed_rpm)
+    }
+}
+
+/// Motor speed levels of the `CR` command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotorSpeed {
+    Default,
+    /// Slowdown steps from 1 to 10 relative to the standard speed.
+    Level(u8),
+    Reset,
+}
+
+impl MotorSpeed {
+    fn code(&self) -> Result<u8, UrgError> {
+        match self {
+            MotorSpeed::Default => Ok(0),
+            MotorSpeed::Level(level @ 1..=10) => Ok(*level),
+            MotorSpeed::Level(level) => Err(UrgError::InvalidArgument(format!(
+                "motor speed level {level} is not in 1..=10"
+            ))),
+            MotorSpeed::Reset => Ok(99),
         }
+    }
+}
 
-        let mut reader = BufReader::new(self.stream.as_ref());
-        if let Some(count) = self.count {
-            let cmd = format!("{}{:0>2}", &self.cmd[..self.cmd.len() - 2], count - 1);
-            if let Err(err) = check_send_cmd_response(&mut reader, &mut self.buffer, &cmd, "99") {
-                return Some(Err(err));
-            } else {
-                self.count = Some(count - 1);
-            }
-        } else if let Err(err) =
-            check_send_cmd_response(&mut reader, &mut self.buffer, &self.cmd, "99")
-        {
-            return Some(Err(err));
+/// Timeouts of a connection; `None` waits forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UrgTimeouts {
+    /// Establishing the TCP connection in `Urg::open_with_timeouts`.
+    pub connect: Option<Duration>,
+    /// Waiting for the response to a command.
+    pub response: Option<Duration>,
+    pub write: Option<Duration>,
+    /// How many scan intervals (the scan period times `scan_skip_count + 1`) a stream frame may
+    /// take on top of `response`.
+    pub frame_intervals: u32,
+}
+
+impl Default for UrgTimeouts {
+    fn default() -> Self {
+        Self {
+            connect: Some(Duration::from_secs(5)),
+            response: Some(Duration::from_secs(2)),
+            write: Some(Duration::from_secs(2)),
+            frame_intervals: 4,
         }
+    }
+}
 
-        match get_raw_data(&mut reader, &mut self.buffer) {
-            Ok((time_stamp, raw_data)) => {
-                if self.has_intensity {
-                    let mut distance = Vec::new();
-                    let mut intensity = Vec::new();
-                    for bytes in raw_data.chunks_exact(6) {
-                        distance.push(decode(&bytes[0..3]));
-                        intensity.push(decode(&bytes[3..6]));
-                    }
-                    Some(Ok(UrgPayload {
-                        time_stamp,
-                        distance,
-                        intensity,
-                    }))
-                } else {
-                    let mut distance = Vec::new();
-                    for bytes in raw_data.chunks_exact(3) {
-                        distance.push(decode(&bytes[0..3]));
-                    }
-                    Some(Ok(UrgPayload {
-                        time_stamp,
-                        distance,
-                        intensity: Vec::new(),
-                    }))
-                }
+impl UrgTimeouts {
+    fn frame(&self, scan_period: Duration, scan_skip_count: u32) -> Option<Duration> {
+        let intervals = scan_skip_count
+            .saturating_add(1)
+            .saturating_mul(self.frame_intervals);
+        Some(
+            self.response?
+                .saturating_add(scan_period.saturating_mul(intervals)),
+        )
+    }
+
+    // Sockets refuse a zero timeout, so it is rejected before anything is sent.
+    fn validate(&self) -> Result<(), UrgError> {
+        let timeouts = [
+            ("connect", self.connect),
+            ("response", self.response),
+            ("write", self.write),
+        ];
+        for (name, timeout) in timeouts {
+            if timeout == Some(Duration::ZERO) {
+                return Err(UrgError::InvalidArgument(format!(
+                    "the {name} timeout is zero; use None to wait forever"
+                )));
             }
-            Err(err) => Some(Err(err)),
         }
+        Ok(())
     }
 }
 
+#[derive(Debug, Default)]
+pub struct UrgPayload {
+    pub time_stamp: u32,
+    pub extended_time_stamp: u64,
+    pub distance: Vec<u32>,
+    pub intensity: Vec<u32>,
+}
+
 #[derive(Debug)]
-pub struct Urg {
-    stream: Arc<TcpStream>,
-    pub is_capturing: bool,
-    pub ip_address: IpAddr,
-    pub port: u16,
+pub struct UrgMultiEchoPayload {
+    pub time_stamp: u32,
+    pub extended_time_stamp: u64,
+    pub distance: Vec<Vec<u32>>,
+    pub intensity: Vec<Vec<u32>>,
 }
 
-impl Urg {
-    pub fn open(ip_address: IpAddr, port: u16) -> io::Result<Self> {
-        let stream = Arc::new(TcpStream::connect((ip_address, port))?);
-        let urg = Self {
-            stream,
-            is_capturing: false,
-            ip_address,
-            port,
-        };
+/// Response to a command sent with `Urg::send_raw`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScipResponse {
+    pub cmd: String,
+    pub status: ScipStatus,
+    pub raw_status: BString,
+    pub lines: Vec<ScipLine>,
+}
 
-        urg.get_version_info()?;
-        Ok(urg)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScipLine {
+    /// The line without its checksum.
+    pub data: BString,
+    /// The line as received, checksum included.
+    pub raw: BString,
+}
+
+impl ScipLine {
+    /// Decodes the data as values of `encoding_len` characters (2, 3 or 4), ignoring a trailing
+    /// partial value. `None` if a character lies outside the encoding, '0' to 'o', as in the text
+    /// of `VV`.
+    pub fn decode(&self, encoding_len: usize) -> Option<Vec<u32>> {
+        let chunks = self.data.chunks_exact(encoding_len.max(1));
+        if chunks
+            .clone()
+            .flatten()
+            .any(|byte| !(0x30..0x70).contains(byte))
+        {
+            return None;
+        }
+        Some(chunks.map(decode).collect())
     }
+}
 
-    pub fn get_version_info(&self) -> io::Result<UrgVersionInfo> {
-        let reader = self.stream.clone();
-        let mut reader = BufReader::new(reader.as_ref());
-        let writer = self.stream.clone();
-        let mut writer = BufWriter::new(writer.as_ref());
-        let mut buffer = Vec::new();
+// Frames kept per endless stream. An iterator that is not polled while other commands run loses
+// the oldest beyond that, which its time stamps reveal. A stream with a scan count keeps all of
+// its frames, at most 99, as the echo of each has to carry the next remaining count.
+const MAX_QUEUED_FRAMES: usize = 64;
 
-        Self::send_cmd(&mut reader, &mut writer, &mut buffer, "VV", "00")?;
-        let vendor_info = Self::recv_b_string_sub(&mut reader, &mut buffer)?;
-        let product_info = Self::recv_b_string_sub(&mut reader, &mut buffer)?;
-        let firmware_version = Self::recv_b_string_sub(&mut reader, &mut buffer)?;
-        let protocol_version = Self::recv_b_string_sub(&mut reader, &mut buffer)?;
-        let serial_number = Self::recv_b_string_sub(&mut reader, &mut buffer)?;
-        _ = recv_data(&mut reader, &mut buffer)?;
+// Scan frames that arrived while a command was waiting for its own response, kept until the
+// iterator of their stream asks for them.
+#[derive(Debug, Default)]
+struct FrameQueue {
+    // The tag of each running stream and whether it has a scan count.
+    active_tags: Vec<(String, bool)>,
+    frames: VecDeque<(String, Vec<u8>)>,
+}
 
-        Ok(UrgVersionInfo {
-            vendor_info,
-            product_info,
-            firmware_version,
-            protocol_version,
-            serial_number,
-        })
+impl FrameQueue {
+    fn add_stream(&mut self, tag: String, has_count: bool) {
+        self.active_tags.push((tag, has_count));
     }
 
-    pub fn get_sensor_params(&self) -> io::Result<UrgSensorParams> {
-        let reader = self.stream.clone();
-        let mut reader = BufReader::new(reader.as_ref());
-        let writer = self.stream.clone();
-        let mut writer = BufWriter::new(writer.as_ref());
-        let mut buffer = Vec::new();
+    fn push(&mut self, tag: String, raw: Vec<u8>) {
+        let Some(&(_, has_count)) = self
+            .active_tags
+            .iter()
+            .find(|(active_tag, _)| *active_tag == tag)
+        else {
+            return;
+        };
+        let queued = self
+            .frames
+            .iter()
+            .filter(|(frame_tag, _)| *frame_tag == tag);
+        if !has_count && queued.count() >= MAX_QUEUED_FRAMES {
+            self.pop(&tag);
+        }
+        self.frames.push_back((tag, raw));
+    }
 
-        Self::send_cmd(&mut reader, &mut writer, &mut buffer, "PP", "00")?;
-        let sensor_model = Self::recv_b_string_sub(&mut reader, &mut buffer)?;
-        let min_distance_mm = Self::recv_b_string_u32(&mut reader, &mut buffer)?;
-        let max_distance_mm = Self::recv_b_string_u32(&mut reader, &mut buffer)?;
-        let angular_area = Self::recv_b_string_u32(&mut reader, &mut buffer)?;
-        let angular_resolution_deg = 360.0 / angular_area as f32;
-        let start_step = Self::recv_b_string_u32(&mut reader, &mut buffer)?;
-        let end_step = Self::recv_b_string_u32(&mut reader, &mut buffer)?;
-        let front_dir_step = Self::recv_b_string_u32(&mut reader, &mut buffer)?;
-        let std_scan_speed_rpm = Self::recv_b_string_u32(&mut reader, &mut buffer)?;
-        // let scan_direction = Self::recv_b_string(&mut reader, &mut buffer)?;
-        _ = recv_data(&mut reader, &mut buffer)?;
+    fn pop(&mut self, tag: &str) -> Option<Vec<u8>> {
+        let pos = self
+            .frames
+            .iter()
+            .position(|(frame_tag, _)| frame_tag == tag)?;
+        self.frames.remove(pos).map(|(_, raw)| raw)
+    }
 
-        Ok(UrgSensorParams {
-            sensor_model,
-            min_distance_mm,
-            max_distance_mm,
-            angular_resolution_deg,
-            start_step,
-            end_step,
-            front_dir_step,
-            std_scan_speed_rpm,
-        })
+    fn remove_stream(&mut self, tag: &str) {
+        self.active_tags.retain(|(active_tag, _)| active_tag != tag);
+        self.frames.retain(|(frame_tag, _)| frame_tag != tag);
     }
+}
 
-    pub fn get_status_info(&self) -> io::Result<UrgStatusInfo> {
-        let reader = self.stream.clone();
-        let mut reader = BufReader::new(reader.as_ref());
-        let writer = self.stream.clone();
-        let mut writer = BufWriter::new(writer.as_ref());
-        let mut buffer = Vec::new();
+struct RawScanIterator<T: Transport> {
+    stream: Arc<Mutex<BufReader<T>>>,
+    frames: Arc<Mutex<FrameQueue>>,
+    time_stamp_tracker: Arc<Mutex<TimeStampTracker>>,
+    is_capturing: Arc<AtomicBool>,
+    reconnect: Arc<Mutex<ReconnectState<T>>>,
+    // The connection the stream was started on, see `ReconnectState::generation`.
+    generation: u64,
+    // Set by `ScanStream` on stop, so that a reconnect backing off gives up.
+    cancel_reconnect: Arc<AtomicBool>,
+    frame_timeout: Option<Duration>,
+    count: Option<u32>,
+    cmd: String,
+    tag: String,
+    // The echo expected for the next frame, rebuilt in place.
+    frame_cmd: String,
+    buffer: Vec<u8>,
+}
 
-        Self::send_cmd(&mut reader, &mut writer, &mut buffer, "II", "00")?;
-        let sensor_model = Self::recv_b_string_sub(&mut reader, &mut buffer)?;
-        let laser_status = Self::recv_b_string_sub(&mut reader, &mut buffer)?;
-        let scanning_speed_rpm = Self::recv_b_string_u32(&mut reader, &mut buffer)?;
-        let measurement_mode = Self::recv_b_string_sub(&mut reader, &mut buffer)?;
-        let communication_speed = Self::recv_b_string_sub(&mut reader, &mut buffer)?;
-        let time_stamp = decode(&Self::recv_b_string_sub(&mut reader, &mut buffer)?);
-        let sensor_status = Self::recv_b_string_sub(&mut reader, &mut buffer)?;
-        _ = recv_data(&mut reader, &mut buffer)?;
+impl<T: Transport> Iterator for RawScanIterator<T> {
+    type Item = Result<(u32, u64, Vec<u8>), UrgError>;
 
-        Ok(UrgStatusInfo {
-            sensor_model,
-            laser_status,
-            scanning_speed_rpm,
-            measurement_mode,
-            communication_speed,
-            time_stamp,
-            sensor_status,
-        })
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame(|reader, buffer, cmd| get_raw_data(reader, buffer, cmd))
     }
+}
 
-    pub fn start_capture(&mut self) -> io::Result<()> {
-        let reader = self.stream.clone();
-        let mut reader = BufReader::new(reader.as_ref());
-        let writer = self.stream.clone();
-        let mut writer = BufWriter::new(writer.as_ref());
-        let mut buffer = Vec::new();
+impl<T: Transport> RawScanIterator<T> {
+    // Checks the echo and status of the next frame and hands the rest to `read_data`, which
+    // returns the time stamp and the decoded data.
+    fn next_frame<D>(
+        &mut self,
+        mut read_data: impl FnMut(&mut dyn BufRead, &mut Vec<u8>, &str) -> Result<(u32, D), UrgError>,
+    ) -> Option<Result<(u32, u64, D), UrgError>> {
+        if self.count == Some(0) {
+            return None;
+        }
 
-        Self::send_cmd(&mut reader, &mut writer, &mut buffer, "BM", "00")?;
-        _ = recv_data(&mut reader, &mut buffer)?;
-        self.is_capturing = true;
+        let stream = Arc::clone(&self.stream);
+        let mut reader = lock(&stream);
+        let res = match self
+            .resume(&mut reader)
+            .and_then(|()| self.read_next(&mut reader, &mut read_data))
+        {
+            Err(err) => reconnect(
+                &stream,
+                reader,
+                &self.reconnect,
+                &self.is_capturing,
+                &self.time_stamp_tracker,
+                Some(&self.cancel_reconnect),
+                err,
+            )
+            .and_then(|mut reader| {
+                self.resume(&mut reader)?;
+                self.read_next(&mut reader, &mut read_data)
+            }),
+            res => res,
+        };
 
-        Ok(())
+        Some(res.map(|(time_stamp, data)| {
+            let extended_time_stamp = lock(&self.time_stamp_tracker).update(time_stamp);
+            (time_stamp, extended_time_stamp, data)
+        }))
     }
 
-    pub fn stop_capture(&mut self) -> io::Result<()> {
-        let reader = self.stream.clone();
-        let mut reader = BufReader::new(reader.as_ref());
-        let writer = self.stream.clone();
-        let mut writer = BufWriter::new(writer.as_ref());
-        let mut buffer = Vec::new();
+    fn read_next<D>(
+        &mut self,
+        stream: &mut BufReader<T>,
+        read_data: &mut impl FnMut(&mut dyn BufRead, &mut Vec<u8>, &str) -> Result<(u32, D), UrgError>,
+    ) -> Result<(u32, D), UrgError> {
+        use std::fmt::Write as _;
 
-        Self::send_cmd(&mut reader, &mut writer, &mut buffer, "QT", "00")?;
-        _ = recv_data(&mut reader, &mut buffer)?;
-        self.is_capturing = false;
+        self.frame_cmd.clear();
+        match self.count {
+            Some(count) => {
+                let prefix = &self.cmd[..self.cmd.len() - 2];
+                _ = write!(self.frame_cmd, "{prefix}{:0>2}", count - 1);
+            }
+            None => self.frame_cmd.push_str(&self.cmd),
+        }
+        let queued = lock(&self.frames).pop(&self.tag);
+        match queued {
+            Some(raw) => self.read_frame(&mut raw.as_slice(), read_data),
+            None => {
+                lock(&self.reconnect).set_read_timeout(stream.get_ref(), self.frame_timeout)?;
+                self.read_frame(stream, read_data)
+            }
+        }
+    }
 
-        Ok(())
+    fn read_frame<D>(
+        &mut self,
+        reader: &mut dyn BufRead,
+        read_data: &mut impl FnMut(&mut dyn BufRead, &mut Vec<u8>, &str) -> Result<(u32, D), UrgError>,
+    ) -> Result<(u32, D), UrgError> {
+        check_send_cmd_response(
+            reader,
+            &mut self.buffer,
+            &self.frame_cmd,
+            Some(&self.tag),
+            Some(&self.frames),
+            "99",
+        )?;
+        if let Some(count) = self.count {
+            self.count = Some(count - 1);
+        }
+        read_data(reader, &mut self.buffer, &self.frame_cmd)
     }
 
-    pub fn reboot(self) -> io::Result<()> {
-        let reader = self.stream.clone();
-        let mut reader = BufReader::new(reader.as_ref());
-        let writer = self.stream;
-        let mut writer = BufWriter::new(writer.as_ref());
-        let mut buffer = Vec::new();
+    // Restarts the stream for the scans left when the connection was replaced since it started.
+    fn resume(&mut self, reader: &mut BufReader<T>) -> Result<(), UrgError> {
+        let generation = lock(&self.reconnect).generation;
+        if generation == self.generation {
+            return Ok(());
+        }
 
-        Self::send_cmd(&mut reader, &mut writer, &mut buffer, "RB", "01")?;
-        _ = recv_data(&mut reader, &mut buffer)?;
-        Self::send_cmd(&mut reader, &mut writer, &mut buffer, "RB", "00")?;
-        _ = recv_data(&mut reader, &mut buffer)?;
+        let cmd = match self.count {
+            Some(count) => {
+                let prefix = &self.cmd[..self.cmd.len() - 2];
+                format!("{prefix}{count:0>2}")
+            }
+            None => self.cmd.clone(),
+        };
+        let tag =
+            Urg::<T>::send_tagged_cmd(reader, &mut self.buffer, &cmd, "00", Some(&self.frames))?;
+        _ = recv_data(reader, &mut self.buffer, &cmd)?;
 
+        let mut frames = lock(&self.frames);
+        frames.remove_stream(&self.tag);
+        frames.add_stream(tag.clone(), self.count.is_some());
+        self.tag = tag;
+        self.generation = generation;
         Ok(())
     }
+}
 
-    pub fn get_distance(
-        &self,
-        start_step: u32,
-        end_step: u32,
-        cluster_count: u32,
-    ) -> io::Result<UrgPayload> {
-        let reader = self.stream.clone();
-        let mut reader = BufReader::new(reader.as_ref());
-        let writer = self.stream.clone();
-        let mut writer = BufWriter::new(writer.as_ref());
-        let mut buffer = Vec::new();
+// Bounds the wait for the answer to `QT` even without response timeouts, so that dropping a stream
+// of a dead sensor returns.
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
 
-        let cmd = format!("GD{:0>4}{:0>4}{:0>2}", start_step, end_step, cluster_count);
-        Self::send_cmd(&mut reader, &mut writer, &mut buffer, &cmd, "00")?;
-        let (time_stamp, raw_data) = get_raw_data(&mut reader, &mut buffer)?;
-        let mut distance = Vec::new();
-        for bytes in raw_data.chunks_exact(3) {
-            distance.push(decode(bytes));
+impl<T: Transport> RawScanIterator<T> {
+    // Stops a stream that has frames left with `QT`, skipping frames until its response.
+    fn stop(&mut self) -> Result<(), UrgError> {
+        lock(&self.frames).remove_stream(&self.tag);
+        if self.count == Some(0) {
+            return Ok(());
         }
+        self.count = Some(0);
 
-        Ok(UrgPayload {
-            time_stamp,
-            distance,
-            intensity: Vec::new(),
-        })
+        let mut reader = lock(&self.stream);
+        lock(&self.reconnect)
+            .set_read_timeout(reader.get_ref(), self.frame_timeout.or(Some(STOP_TIMEOUT)))?;
+        let tag = write_tagged_cmd(reader.get_mut(), "QT")?;
+        check_send_cmd_response(
+            &mut *reader,
+            &mut self.buffer,
+            "QT",
+            Some(&tag),
+            Some(&self.frames),
+            "00",
+        )?;
+        _ = recv_data(&mut *reader, &mut self.buffer, "QT")?;
+        // `QT` also turns the laser off.
+        self.is_capturing.store(false, Ordering::Relaxed);
+        Ok(())
     }
+}
 
-    pub fn get_distance_multi(
-        &self,
-        start_step: u32,
-        end_step: u32,
-        cluster_count: u32,
-        scan_skip_count: u32,
-        num_of_scan: u32,
-    ) -> io::Result<UrgPayloadIterator> {
-        let reader = self.stream.clone();
-        let mut reader = BufReader::new(reader.as_ref());
-        let writer = self.stream.clone();
-        let mut writer = BufWriter::new(writer.as_ref());
-        let mut buffer = Vec::new();
+impl<T: Transport> Drop for RawScanIterator<T> {
+    fn drop(&mut self) {
+        _ = self.stop();
+    }
+}
 
-        let cmd = format!(
-            "MD{:0>4}{:0>4}{:0>2}{:0>1}{:0>2}",
-            start_step, end_step, cluster_count, scan_skip_count, num_of_scan
-        );
-        Self::send_cmd(&mut reader, &mut writer, &mut buffer, &cmd, "00")?;
-        _ = recv_data(&mut reader, &mut buffer)?;
+pub struct UrgPayloadIterator<T: Transport = TcpStream> {
+    raw: RawScanIterator<T>,
+    has_intensity: bool,
+    encoding_len: usize,
+}
 
-        let count = if num_of_scan == 0 {
-            None
-        } else {
-            Some(num_of_scan)
-        };
+impl<T: Transport> UrgPayloadIterator<T> {
+    /// Stops the stream with `QT`, which also turns the laser off. Dropping the iterator does the
+    /// same, ignoring errors.
+    pub fn cancel(mut self) -> Result<(), UrgError> {
+        self.raw.stop()
+    }
+
+    /// Like `next`, but decodes into `payload`, reusing its vectors. Once they have grown to the
+    /// scan size, reading a frame does not allocate.
+    pub fn next_into(&mut self, payload: &mut UrgPayload) -> Option<Result<(), UrgError>> {
+        let (encoding_len, has_intensity) = (self.encoding_len, self.has_intensity);
+        let res = self.raw.next_frame(|reader, buffer, cmd| {
+            recv_scan_into(reader, buffer, cmd, encoding_len, has_intensity, payload)
+                .map(|()| (payload.time_stamp, ()))
+        })?;
+        Some(res.map(|(_, extended_time_stamp, ())| {
+            payload.extended_time_stamp = extended_time_stamp;
+        }))
+    }
+}
+
+impl<T: Transport> Iterator for UrgPayloadIterator<T> {
+    type Item = Result<UrgPayload, UrgError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut payload = UrgPayload::default();
+        Some(self.next_into(&mut payload)?.map(|()| payload))
+    }
+}
+
+pub struct UrgMultiEchoPayloadIterator<T: Transport = TcpStream> {
+    raw: RawScanIterator<T>,
+    has_intensity: bool,
+}
+
+impl<T: Transport> UrgMultiEchoPayloadIterator<T> {
+    /// Stops the stream with `QT`, which also turns the laser off. Dropping the iterator does the
+    /// same, ignoring errors.
+    pub fn cancel(mut self) -> Result<(), UrgError> {
+        self.raw.stop()
+    }
+}
+
+impl<T: Transport> Iterator for UrgMultiEchoPayloadIterator<T> {
+    type Item = Result<UrgMultiEchoPayload, UrgError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.raw.next()? {
+            Ok((time_stamp, extended_time_stamp, raw_data)) => {
+                let (distance, intensity) = decode_multi_echo(&raw_data, self.has_intensity);
+                Some(Ok(UrgMultiEchoPayload {
+                    time_stamp,
+                    extended_time_stamp,
+                    distance,
+                    intensity,
+                }))
+            }
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Urg<T: Transport = TcpStream> {
+    // One buffered reader for the whole connection, so that bytes read ahead of one response are
+    // kept for the next.
+    stream: Arc<Mutex<BufReader<T>>>,
+    frames: Arc<Mutex<FrameQueue>>,
+    time_stamp_tracker: Arc<Mutex<TimeStampTracker>>,
+    scanning_speed_rpm: Mutex<Option<u32>>,
+    capabilities: Mutex<Option<UrgCapabilities>>,
+    negotiated_protocol: ScipProtocol,
+    is_capturing: Arc<AtomicBool>,
+    timeouts: UrgTimeouts,
+    reconnect: Arc<Mutex<ReconnectState<T>>>,
+    /// The address passed to `Urg::open`; unspecified over other transports.
+    #[deprecated(note = "use `peer_addr`")]
+    pub ip_address: IpAddr,
+    #[deprecated(note = "use `peer_addr`")]
+    pub port: u16,
+}
+
+impl Urg {
+    pub fn open(ip_address: IpAddr, port: u16) -> Result<Self, UrgError> {
+        Self::open_with_timeouts(ip_address, port, UrgTimeouts::default())
+    }
+
+    pub fn open_with_timeouts(
+        ip_address: IpAddr,
+        port: u16,
+        timeouts: UrgTimeouts,
+    ) -> Result<Self, UrgError> {
+        timeouts.validate()?;
+        let address = SocketAddr::new(ip_address, port);
+        let stream = match timeouts.connect {
+            Some(timeout) => TcpStream::connect_timeout(&address, timeout)?,
+            None => TcpStream::connect(address)?,
+        };
+        let mut urg = Self::with_timeouts(stream, timeouts)?;
+        #[allow(deprecated)]
+        {
+            urg.ip_address = ip_address;
+            urg.port = port;
+        }
+        Ok(urg)
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddr, UrgError> {
+        Ok(lock(&self.stream).get_ref().peer_addr()?)
+    }
+}
+
+impl<T: Transport> Urg<T> {
+    /// Connects over `transport`, switching a sensor that booted in SCIP 1.1 to SCIP 2.0.
+    pub fn from_transport(transport: T) -> Result<Self, UrgError> {
+        Self::with_timeouts(transport, UrgTimeouts::default())
+    }
+
+    fn with_timeouts(transport: T, timeouts: UrgTimeouts) -> Result<Self, UrgError> {
+        timeouts.validate()?;
+        transport.set_read_timeout(timeouts.response)?;
+        transport.set_write_timeout(timeouts.write)?;
+        let mut reader = BufReader::new(transport);
+        let negotiated_protocol = Self::negotiate_protocol(&mut reader, &mut Vec::new())?;
+        Self::with_protocol(reader, negotiated_protocol, timeouts)
+    }
+
+    fn with_protocol(
+        reader: BufReader<T>,
+        negotiated_protocol: ScipProtocol,
+        timeouts: UrgTimeouts,
+    ) -> Result<Self, UrgError> {
+        reader.get_ref().set_write_timeout(timeouts.write)?;
+        #[allow(deprecated)]
+        let urg = Self {
+            stream: Arc::new(Mutex::new(reader)),
+            frames: Arc::new(Mutex::new(FrameQueue::default())),
+            time_stamp_tracker: Arc::new(Mutex::new(TimeStampTracker::new())),
+            scanning_speed_rpm: Mutex::new(None),
+            capabilities: Mutex::new(None),
+            negotiated_protocol,
+            is_capturing: Arc::new(AtomicBool::new(false)),
+            timeouts,
+            reconnect: Arc::new(Mutex::new(ReconnectState::new(timeouts))),
+            ip_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 0,
+        };
+
+        urg.get_version_info()?;
+        Ok(urg)
+    }
+
+    fn negotiate_protocol(
+        reader: &mut BufReader<impl Read + Write>,
+        buffer: &mut Vec<u8>,
+    ) -> Result<ScipProtocol, UrgError> {
+        let writer = reader.get_mut();
+        writer.write_all(b"VV\n")?;
+        writer.flush()?;
+        let has_stale_stream = skip_to_echo(reader, buffer, "VV")?;
+        if !is_scip1_response(reader, buffer)? {
+            if has_stale_stream {
+                // Stops the stream, skipping its frames up to the answer.
+                Self::send_tagged_cmd(reader, buffer, "QT", "00", None)?;
+                _ = recv_data(reader, buffer, "QT")?;
+            }
+            return Ok(ScipProtocol::Scip2);
+        }
+
+        let writer = reader.get_mut();
+        writer.write_all(b"SCIP2.0\n")?;
+        writer.flush()?;
+        check_scip2_switch_response(reader, buffer)?;
+        Ok(ScipProtocol::Scip1SwitchedToScip2)
+    }
+
+    pub fn get_version_info(&self) -> Result<UrgVersionInfo, UrgError> {
+        self.with_reader(|reader, buffer| {
+            self.send_cmd(reader, buffer, "VV", "00")?;
+            let fields = recv_fields(reader, buffer, "VV")?;
+            UrgVersionInfo::from_fields(fields, self.negotiated_protocol)
+        })
+    }
+
+    pub fn get_sensor_params(&self) -> Result<UrgSensorParams, UrgError> {
+        self.with_reader(|reader, buffer| {
+            self.send_cmd(reader, buffer, "PP", "00")?;
+            let fields = recv_fields(reader, buffer, "PP")?;
+            UrgSensorParams::from_fields(fields)
+        })
+    }
+
+    pub fn get_status_info(&self) -> Result<UrgStatusInfo, UrgError> {
+        self.with_reader(|reader, buffer| {
+            self.send_cmd(reader, buffer, "II", "00")?;
+            let fields = recv_fields(reader, buffer, "II")?;
+            let status_info = UrgStatusInfo::from_fields(fields)?;
+            *lock(&self.scanning_speed_rpm) = Some(status_info.scanning_speed_rpm);
+            Ok(status_info)
+        })
+    }
+
+    /// Whether the laser was turned on with `start_capture` and not turned off since.
+    pub fn is_capturing(&self) -> bool {
+        self.is_capturing.load(Ordering::Relaxed)
+    }
+
+    pub fn timeouts(&self) -> UrgTimeouts {
+        self.timeouts
+    }
+
+    /// Applies from the next command on. Running streams keep their frame timeout.
+    pub fn set_timeouts(&mut self, timeouts: UrgTimeouts) -> Result<(), UrgError> {
+        timeouts.validate()?;
+        lock(&self.stream)
+            .get_ref()
+            .set_write_timeout(timeouts.write)?;
+        self.timeouts = timeouts;
+        lock(&self.reconnect).timeouts = timeouts;
+        Ok(())
+    }
+
+    pub fn start_capture(&mut self) -> Result<(), UrgError> {
+        self.with_reader(|reader, buffer| {
+            self.send_cmd(reader, buffer, "BM", "00")?;
+            _ = recv_data(reader, buffer, "BM")?;
+            self.is_capturing.store(true, Ordering::Relaxed);
+
+            Ok(())
+        })
+    }
+
+    pub fn stop_capture(&mut self) -> Result<(), UrgError> {
+        self.with_reader(|reader, buffer| {
+            self.send_cmd(reader, buffer, "QT", "00")?;
+            _ = recv_data(reader, buffer, "QT")?;
+            self.is_capturing.store(false, Ordering::Relaxed);
+
+            Ok(())
+        })
+    }
+
+    pub fn set_motor_speed(&mut self, speed: MotorSpeed) -> Result<(), UrgError> {
+        let cmd = format!("CR{:0>2}", speed.code()?);
+        self.with_reader(|reader, buffer| {
+            match self.send_cmd(reader, buffer, &cmd, "00") {
+                Ok(_) => _ = recv_data(reader, buffer, &cmd)?,
+                Err(UrgError::Status {
+                    status: ScipStatus::AlreadyInEffect,
+                    ..
+                }) => {}
+                Err(err) => return Err(err),
+            }
+            Ok(())
+        })?;
+        lock(&self.reconnect).motor_speed = Some(speed);
+
+        // The resulting speed depends on the model, so read it back for `scan_period`.
+        self.get_status_info()?;
+        Ok(())
+    }
+
+    pub fn set_high_sensitivity(&mut self, enable: bool) -> Result<(), UrgError> {
+        self.with_reader(|reader, buffer| {
+            let cmd = if enable { "HS1" } else { "HS0" };
+            match self.send_cmd(reader, buffer, cmd, "00") {
+                Ok(_) => _ = recv_data(reader, buffer, cmd)?,
+                Err(UrgError::Status {
+                    status: ScipStatus::AlreadyInEffect,
+                    ..
+                }) => {}
+                Err(err) => return Err(err),
+            }
+            Ok(())
+        })?;
+        lock(&self.reconnect).high_sensitivity = Some(enable);
+        Ok(())
+    }
+
+    /// Time per scan at the current motor speed.
+    pub fn scan_period(&self) -> Result<Duration, UrgError> {
+        let scanning_speed_rpm = *lock(&self.scanning_speed_rpm);
+        match scanning_speed_rpm {
+            Some(scanning_speed_rpm) => Ok(scan_period(scanning_speed_rpm)),
+            None => Ok(self.get_status_info()?.scan_period()),
+        }
+    }
+
+    pub fn reboot(self) -> Result<(), UrgError> {
+        let mut reader = lock(&self.stream);
+        let mut buffer = Vec::new();
+
+        self.send_cmd(&mut *reader, &mut buffer, "RB", "01")?;
+        _ = recv_data(&mut *reader, &mut buffer, "RB")?;
+        self.send_cmd(&mut *reader, &mut buffer, "RB", "00")?;
+        _ = recv_data(&mut *reader, &mut buffer, "RB")?;
+
+        Ok(())
+    }
+
+    pub fn get_distance(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+    ) -> Result<UrgPayload, UrgError> {
+        let mut payload = UrgPayload::default();
+        self.get_distance_into(start_step, end_step, cluster_count, &mut payload)?;
+        Ok(payload)
+    }
+
+    /// Like `get_distance`, but decodes into `payload`, reusing its vectors.
+    pub fn get_distance_into(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+        payload: &mut UrgPayload,
+    ) -> Result<(), UrgError> {
+        let cmd = format!("GD{:0>4}{:0>4}{:0>2}", start_step, end_step, cluster_count);
+        self.get_scan_into(&cmd, 3, false, payload)
+    }
+
+    pub fn get_distance_multi(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+        scan_skip_count: u32,
+        num_of_scan: u32,
+    ) -> Result<UrgPayloadIterator<T>, UrgError> {
+        let cmd = format!(
+            "MD{:0>4}{:0>4}{:0>2}{:0>1}{:0>2}",
+            start_step, end_step, cluster_count, scan_skip_count, num_of_scan
+        );
 
         Ok(UrgPayloadIterator {
-            stream: self.stream.clone(),
-            count,
-            cmd,
+            raw: self.start_stream(cmd, scan_skip_count, num_of_scan)?,
+            has_intensity: false,
+            encoding_len: 3,
+        })
+    }
+
+    pub fn get_distance_short(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+    ) -> Result<UrgPayload, UrgError> {
+        let mut payload = UrgPayload::default();
+        self.get_distance_short_into(start_step, end_step, cluster_count, &mut payload)?;
+        Ok(payload)
+    }
+
+    /// Like `get_distance_short`, but decodes into `payload`, reusing its vectors.
+    pub fn get_distance_short_into(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+        payload: &mut UrgPayload,
+    ) -> Result<(), UrgError> {
+        let cmd = format!("GS{:0>4}{:0>4}{:0>2}", start_step, end_step, cluster_count);
+        self.get_scan_into(&cmd, 2, false, payload)
+    }
+
+    pub fn get_distance_short_multi(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+        scan_skip_count: u32,
+        num_of_scan: u32,
+    ) -> Result<UrgPayloadIterator<T>, UrgError> {
+        let cmd = format!(
+            "MS{:0>4}{:0>4}{:0>2}{:0>1}{:0>2}",
+            start_step, end_step, cluster_count, scan_skip_count, num_of_scan
+        );
+
+        Ok(UrgPayloadIterator {
+            raw: self.start_stream(cmd, scan_skip_count, num_of_scan)?,
             has_intensity: false,
-            buffer,
+            encoding_len: 2,
         })
     }
 
@@ -324,27 +1339,22 @@ impl Urg {
         start_step: u32,
         end_step: u32,
         cluster_count: u32,
-    ) -> io::Result<UrgPayload> {
-        let reader = self.stream.clone();
-        let mut reader = BufReader::new(reader.as_ref());
-        let writer = self.stream.clone();
-        let mut writer = BufWriter::new(writer.as_ref());
-        let mut buffer = Vec::new();
+    ) -> Result<UrgPayload, UrgError> {
+        let mut payload = UrgPayload::default();
+        self.get_distance_intensity_into(start_step, end_step, cluster_count, &mut payload)?;
+        Ok(payload)
+    }
 
+    /// Like `get_distance_intensity`, but decodes into `payload`, reusing its vectors.
+    pub fn get_distance_intensity_into(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+        payload: &mut UrgPayload,
+    ) -> Result<(), UrgError> {
         let cmd = format!("GE{:0>4}{:0>4}{:0>2}", start_step, end_step, cluster_count);
-        Self::send_cmd(&mut reader, &mut writer, &mut buffer, &cmd, "00")?;
-        let (time_stamp, raw_data) = get_raw_data(&mut reader, &mut buffer)?;
-        let mut distance = Vec::new();
-        let mut intensity = Vec::new();
-        for bytes in raw_data.chunks_exact(6) {
-            distance.push(decode(&bytes[0..3]));
-            intensity.push(decode(&bytes[3..6]));
-        }
-        Ok(UrgPayload {
-            time_stamp,
-            distance,
-            intensity,
-        })
+        self.get_scan_into(&cmd, 3, true, payload)
     }
 
     pub fn get_distance_intensity_multi(
@@ -354,19 +1364,137 @@ impl Urg {
         cluster_count: u32,
         scan_skip_count: u32,
         num_of_scan: u32,
-    ) -> io::Result<UrgPayloadIterator> {
-        let reader = self.stream.clone();
-        let mut reader = BufReader::new(reader.as_ref());
-        let writer = self.stream.clone();
-        let mut writer = BufWriter::new(writer.as_ref());
-        let mut buffer = Vec::new();
-
+    ) -> Result<UrgPayloadIterator<T>, UrgError> {
         let cmd = format!(
             "ME{:0>4}{:0>4}{:0>2}{:0>1}{:0>2}",
             start_step, end_step, cluster_count, scan_skip_count, num_of_scan
         );
-        Self::send_cmd(&mut reader, &mut writer, &mut buffer, &cmd, "00")?;
-        _ = recv_data(&mut reader, &mut buffer)?;
+
+        Ok(UrgPayloadIterator {
+            raw: self.start_stream(cmd, scan_skip_count, num_of_scan)?,
+            has_intensity: true,
+            encoding_len: 3,
+        })
+    }
+
+    pub fn get_multi_echo_distance(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+    ) -> Result<UrgMultiEchoPayload, UrgError> {
+        let cmd = format!("HD{:0>4}{:0>4}{:0>2}", start_step, end_step, cluster_count);
+        self.check_supported(&cmd)?;
+        self.with_reader(|reader, buffer| {
+            self.send_cmd(reader, buffer, &cmd, "00")?;
+            let (time_stamp, raw_data) = get_raw_data(reader, buffer, &cmd)?;
+            let extended_time_stamp = lock(&self.time_stamp_tracker).update(time_stamp);
+            let (distance, intensity) = decode_multi_echo(&raw_data, false);
+
+            Ok(UrgMultiEchoPayload {
+                time_stamp,
+                extended_time_stamp,
+                distance,
+                intensity,
+            })
+        })
+    }
+
+    pub fn get_multi_echo_distance_multi(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+        scan_skip_count: u32,
+        num_of_scan: u32,
+    ) -> Result<UrgMultiEchoPayloadIterator<T>, UrgError> {
+        let cmd = format!(
+            "ND{:0>4}{:0>4}{:0>2}{:0>1}{:0>2}",
+            start_step, end_step, cluster_count, scan_skip_count, num_of_scan
+        );
+
+        Ok(UrgMultiEchoPayloadIterator {
+            raw: self.start_stream(cmd, scan_skip_count, num_of_scan)?,
+            has_intensity: false,
+        })
+    }
+
+    pub fn get_multi_echo_distance_intensity(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+    ) -> Result<UrgMultiEchoPayload, UrgError> {
+        let cmd = format!("HE{:0>4}{:0>4}{:0>2}", start_step, end_step, cluster_count);
+        self.check_supported(&cmd)?;
+        self.with_reader(|reader, buffer| {
+            self.send_cmd(reader, buffer, &cmd, "00")?;
+            let (time_stamp, raw_data) = get_raw_data(reader, buffer, &cmd)?;
+            let extended_time_stamp = lock(&self.time_stamp_tracker).update(time_stamp);
+            let (distance, intensity) = decode_multi_echo(&raw_data, true);
+
+            Ok(UrgMultiEchoPayload {
+                time_stamp,
+                extended_time_stamp,
+                distance,
+                intensity,
+            })
+        })
+    }
+
+    pub fn get_multi_echo_distance_intensity_multi(
+        &self,
+        start_step: u32,
+        end_step: u32,
+        cluster_count: u32,
+        scan_skip_count: u32,
+        num_of_scan: u32,
+    ) -> Result<UrgMultiEchoPayloadIterator<T>, UrgError> {
+        let cmd = format!(
+            "NE{:0>4}{:0>4}{:0>2}{:0>1}{:0>2}",
+            start_step, end_step, cluster_count, scan_skip_count, num_of_scan
+        );
+
+        Ok(UrgMultiEchoPayloadIterator {
+            raw: self.start_stream(cmd, scan_skip_count, num_of_scan)?,
+            has_intensity: true,
+        })
+    }
+
+    fn get_scan_into(
+        &self,
+        cmd: &str,
+        encoding_len: usize,
+        has_intensity: bool,
+        payload: &mut UrgPayload,
+    ) -> Result<(), UrgError> {
+        self.check_supported(cmd)?;
+        self.with_reader(|reader, buffer| {
+            self.send_cmd(reader, buffer, cmd, "00")?;
+            recv_scan_into(reader, buffer, cmd, encoding_len, has_intensity, payload)?;
+            payload.extended_time_stamp = lock(&self.time_stamp_tracker).update(payload.time_stamp);
+            Ok(())
+        })
+    }
+
+    fn start_stream(
+        &self,
+        cmd: String,
+        scan_skip_count: u32,
+        num_of_scan: u32,
+    ) -> Result<RawScanIterator<T>, UrgError> {
+        self.check_supported(&cmd)?;
+        let frame_timeout = match self.timeouts.response {
+            Some(_) => self
+                .timeouts
+                .frame(self.current_scan_period()?, scan_skip_count),
+            None => None,
+        };
+        let (tag, generation) = self.with_reader(|reader, buffer| {
+            let tag = self.send_cmd(reader, buffer, &cmd, "00")?;
+            _ = recv_data(reader, buffer, &cmd)?;
+            Ok((tag, lock(&self.reconnect).generation))
+        })?;
 
         let count = if num_of_scan == 0 {
             None
@@ -374,136 +1502,787 @@ impl Urg {
             Some(num_of_scan)
         };
 
-        Ok(UrgPayloadIterator {
+        lock(&self.frames).add_stream(tag.clone(), count.is_some());
+
+        Ok(RawScanIterator {
             stream: self.stream.clone(),
+            frames: self.frames.clone(),
+            time_stamp_tracker: self.time_stamp_tracker.clone(),
+            is_capturing: self.is_capturing.clone(),
+            reconnect: self.reconnect.clone(),
+            generation,
+            cancel_reconnect: Arc::new(AtomicBool::new(false)),
+            frame_timeout,
             count,
             cmd,
-            has_intensity: true,
-            buffer,
+            tag,
+            frame_cmd: String::new(),
+            buffer: Vec::new(),
         })
     }
 
-    fn recv_b_string(reader: &mut impl BufRead, buffer: &mut Vec<u8>) -> io::Result<BString> {
-        let n = recv_data(reader, buffer)?;
-        if n < 2 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("can not convert to BString. recv bytes len:{n}"),
-            ));
+    // The motor speed read by `II` if any, which `set_motor_speed` does, and else the standard
+    // speed of the model. Unlike `scan_period`, this never sends `II` itself.
+    fn current_scan_period(&self) -> Result<Duration, UrgError> {
+        let scanning_speed_rpm = *lock(&self.scanning_speed_rpm);
+        match scanning_speed_rpm {
+            Some(scanning_speed_rpm) => Ok(scan_period(scanning_speed_rpm)),
+            None => Ok(self.get_capabilities()?.scan_period()),
         }
-        Ok(BString::new(buffer[..n - 2].to_vec()))
     }
 
-    fn recv_b_string_sub(reader: &mut impl BufRead, buffer: &mut Vec<u8>) -> io::Result<BString> {
-        let str = Self::recv_b_string(reader, buffer)?;
-        let len = str.len();
-        if len < 6 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("can not sub BString. BString:{str} length: {len}"),
-            ));
+    // Runs `f` on the connection. With a reconnect policy, a connection that fails is replaced and
+    // `f` runs once more on the new one.
+    fn with_reader<R>(
+        &self,
+        mut f: impl FnMut(&mut BufReader<T>, &mut Vec<u8>) -> Result<R, UrgError>,
+    ) -> Result<R, UrgError> {
+        let mut reader = lock(&self.stream);
+        let mut buffer = Vec::new();
+        match f(&mut reader, &mut buffer) {
+            Err(err) => {
+                let mut reader = reconnect(
+                    &self.stream,
+                    reader,
+                    &self.reconnect,
+                    &self.is_capturing,
+                    &self.time_stamp_tracker,
+                    None,
+                    err,
+                )?;
+                f(&mut reader, &mut buffer)
+            }
+            res => res,
         }
-        Ok(BString::new(str[5..len - 1].to_vec()))
     }
 
-    fn recv_b_string_u32(reader: &mut impl BufRead, buffer: &mut Vec<u8>) -> io::Result<u32> {
-        Self::recv_b_string_sub(reader, buffer)?
-            .to_str()
-            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
-            .parse()
-            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    // Scan frames of running streams that arrive before the response are queued for their
+    // iterators.
+    fn send_cmd(
+        &self,
+        reader: &mut BufReader<T>,
+        buffer: &mut Vec<u8>,
+        cmd: &str,
+        ok_status: &str,
+    ) -> Result<String, UrgError> {
+        lock(&self.reconnect).set_read_timeout(reader.get_ref(), self.timeouts.response)?;
+        Self::send_tagged_cmd(reader, buffer, cmd, ok_status, Some(&self.frames))
     }
 
-    fn send_cmd(
-        reader: &mut impl BufRead,
-        writer: &mut impl Write,
+    // Sends `cmd` with a fresh string tag, which is returned for matching later responses.
+    fn send_tagged_cmd(
+        reader: &mut BufReader<impl Read + Write>,
         buffer: &mut Vec<u8>,
         cmd: &str,
         ok_status: &str,
-    ) -> io::Result<()> {
-        writer.write_all(cmd.as_bytes())?;
-        writer.write_all(&[b'\n'])?;
-        writer.flush()?;
-        check_send_cmd_response(reader, buffer, cmd, ok_status)
+        frames: Option<&Mutex<FrameQueue>>,
+    ) -> Result<String, UrgError> {
+        let tag = write_tagged_cmd(reader.get_mut(), cmd)?;
+        check_send_cmd_response(reader, buffer, cmd, Some(&tag), frames, ok_status)?;
+        Ok(tag)
     }
-}
 
-fn decode(raw: &[u8]) -> u32 {
-    let mut res = 0;
-    for byte in raw {
-        res <<= 6;
-        res += ((byte - 0x30) & 0b00111111) as u32;
+    /// Sends any SCIP command and returns its response, whatever the status.
+    ///
+    /// The echo and every checksum are verified. For a streaming command only the first response
+    /// is read.
+    pub fn send_raw(&self, cmd: &str) -> Result<ScipResponse, UrgError> {
+        if cmd.is_empty() || cmd.contains(['\n', ';']) {
+            return Err(UrgError::InvalidArgument(format!(
+                "{cmd:?} is not a single SCIP command"
+            )));
+        }
+        self.with_reader(|reader, buffer| {
+            lock(&self.reconnect).set_read_timeout(reader.get_ref(), self.timeouts.response)?;
+            let tag = write_tagged_cmd(reader.get_mut(), cmd)?;
+            check_echo(reader, buffer, cmd, Some(&tag), Some(&self.frames))?;
+            let raw_status = BString::from(recv_status(reader, buffer, cmd)?);
+            let mut lines = Vec::new();
+            loop {
+                let n = recv_data(reader, buffer, cmd)?;
+                if n == 1 {
+                    break;
+                }
+                let line_index = lines.len() + 2;
+                if n < 3 {
+                    return Err(UrgError::MalformedLine {
+                        cmd: cmd.to_string(),
+                        line_index,
+                        line: BString::new(buffer[..n].to_vec()),
+                    });
+                }
+                if is_field_cmd(cmd) {
+                    verify_field_checksum(&buffer[..n - 1], cmd, line_index)?;
+                } else {
+                    verify_checksum(&buffer[..n - 1], cmd, line_index)?;
+                }
+                lines.push(ScipLine {
+                    data: BString::new(buffer[..n - 2].to_vec()),
+                    raw: BString::new(buffer[..n - 1].to_vec()),
+                });
+            }
+
+            Ok(ScipResponse {
+                cmd: cmd.to_string(),
+                status: ScipStatus::new(cmd, &raw_status),
+                raw_status,
+                lines,
+            })
+        })
     }
-    res
 }
 
-fn get_raw_data(reader: &mut impl BufRead, buffer: &mut Vec<u8>) -> io::Result<(u32, Vec<u8>)> {
-    let n = recv_data(reader, buffer)?;
-    if n != 6 {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            format!(
-                "get_raw_data failed. recv wrong timestamp data {:?}",
-                buffer
-            ),
-        ));
+// Skips responses up to the echo of the untagged `cmd`, such as frames of a stream left running by
+// an earlier session. Returns whether any were skipped.
+fn skip_to_echo(
+    reader: &mut (impl BufRead + ?Sized),
+    buffer: &mut Vec<u8>,
+    cmd: &str,
+) -> Result<bool, UrgError> {
+    let mut has_skipped = false;
+    loop {
+        let n = recv_data(reader, buffer, cmd)?;
+        if &buffer[..n - 1] == cmd.as_bytes() {
+            return Ok(has_skipped);
+        }
+        if n > 1 {
+            skip_response(reader, buffer, cmd, None)?;
+            has_skipped = true;
+        }
+    }
+}
+
+// Reads the response to `VV` after its echo. SCIP 1.1 answers with a single status character and
+// no checksum.
+fn is_scip1_response(
+    reader: &mut (impl BufRead + ?Sized),
+    buffer: &mut Vec<u8>,
+) -> Result<bool, UrgError> {
+    let is_scip1 = recv_data(reader, buffer, "VV")? == 2;
+    while recv_data(reader, buffer, "VV")? > 1 {}
+    Ok(is_scip1)
+}
+
+fn check_scip2_switch_response(
+    reader: &mut (impl BufRead + ?Sized),
+    buffer: &mut Vec<u8>,
+) -> Result<(), UrgError> {
+    check_echo(reader, buffer, "SCIP2.0", None, None)?;
+    let n = recv_data(reader, buffer, "SCIP2.0")?;
+    let raw = BString::new(buffer[..n - 1].to_vec());
+    while recv_data(reader, buffer, "SCIP2.0")? > 1 {}
+    if raw != "0" {
+        return Err(UrgError::Status {
+            cmd: "SCIP2.0".to_string(),
+            status: ScipStatus::new("SCIP2.0", &raw),
+            raw,
+        });
     }
-    let time_stamp = decode(&buffer[..4]);
+    Ok(())
+}
+
+fn write_tagged_cmd(writer: &mut impl Write, cmd: &str) -> Result<String, UrgError> {
+    let tag = next_tag();
+    writer.write_all(format!("{cmd};{tag}\n").as_bytes())?;
+    writer.flush()?;
+    Ok(tag)
+}
+
+fn scan_period(scan_speed_rpm: u32) -> Duration {
+    Duration::from_secs(60) / scan_speed_rpm.max(1)
+}
+
+fn lock<T>(stream: &Mutex<T>) -> MutexGuard<'_, T> {
+    stream.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn decode(raw: &[u8]) -> u32 {
+    let mut res = 0;
+    for byte in raw {
+        res <<= 6;
+        res += ((byte - 0x30) & 0b00111111) as u32;
+    }
+    res
+}
+
+// Each step holds one or more echoes separated by '&', each echo being a 3-char distance
+// optionally followed by a 3-char intensity.
+fn decode_multi_echo(raw: &[u8], has_intensity: bool) -> (Vec<Vec<u32>>, Vec<Vec<u32>>) {
+    let echo_len = if has_intensity { 6 } else { 3 };
+    let mut distance: Vec<Vec<u32>> = Vec::new();
+    let mut intensity: Vec<Vec<u32>> = Vec::new();
+    let mut pos = 0;
+    while pos + echo_len <= raw.len() {
+        let is_next_echo = raw[pos] == b'&';
+        if is_next_echo {
+            pos += 1;
+            if pos + echo_len > raw.len() {
+                break;
+            }
+        }
+        let echo = &raw[pos..pos + echo_len];
+        if !is_next_echo || distance.is_empty() {
+            distance.push(Vec::new());
+            if has_intensity {
+                intensity.push(Vec::new());
+            }
+        }
+        distance.last_mut().unwrap().push(decode(&echo[0..3]));
+        if has_intensity {
+            intensity.last_mut().unwrap().push(decode(&echo[3..6]));
+        }
+        pos += echo_len;
+    }
+    (distance, intensity)
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    let sum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    (sum & 0b00111111) + 0x30
+}
+
+// `line` is a received line without its trailing LF, ending with the checksum byte.
+fn verify_checksum(line: &[u8], cmd: &str, line_index: usize) -> Result<(), UrgError> {
+    let (&actual, data) = line.split_last().unwrap_or((&0, &[]));
+    let expected = checksum(data);
+    if expected == actual {
+        return Ok(());
+    }
+    Err(checksum_error(line, cmd, line_index, expected, actual))
+}
+
+// Like `verify_checksum`, but for `KEY:value;` lines, whose sum excludes the trailing ';'.
+fn verify_field_checksum(line: &[u8], cmd: &str, line_index: usize) -> Result<(), UrgError> {
+    let (&actual, data) = line.split_last().unwrap_or((&0, &[]));
+    let expected = checksum(data.strip_suffix(b";").unwrap_or(data));
+    if expected == actual {
+        return Ok(());
+    }
+    Err(checksum_error(line, cmd, line_index, expected, actual))
+}
+
+// Commands whose data lines are `KEY:value;` fields.
+fn is_field_cmd(cmd: &str) -> bool {
+    matches!(cmd, "VV" | "PP" | "II")
+}
+
+fn checksum_error(line: &[u8], cmd: &str, line_index: usize, expected: u8, actual: u8) -> UrgError {
+    UrgError::Checksum {
+        cmd: cmd.to_string(),
+        line_index,
+        expected,
+        actual,
+        line: BString::new(line.to_vec()),
+    }
+}
+
+fn get_raw_data(
+    reader: &mut (impl BufRead + ?Sized),
+    buffer: &mut Vec<u8>,
+    cmd: &str,
+) -> Result<(u32, Vec<u8>), UrgError> {
+    let time_stamp = recv_time_stamp(reader, buffer, cmd)?;
 
     let mut raw_data: Vec<u8> = Vec::new();
+    let mut line_index = 3;
     loop {
-        let n = recv_data(reader, buffer)?;
+        let n = recv_data(reader, buffer, cmd)?;
         if n == 1 {
             break;
-        } else {
-            raw_data.extend_from_slice(&buffer[..n - 2]);
+        } else if n < 3 {
+            return Err(UrgError::MalformedLine {
+                cmd: cmd.to_string(),
+                line_index,
+                line: BString::new(buffer[..n].to_vec()),
+            });
         }
+        verify_checksum(&buffer[..n - 1], cmd, line_index)?;
+        raw_data.extend_from_slice(&buffer[..n - 2]);
+        line_index += 1;
     }
     Ok((time_stamp, raw_data))
 }
 
+fn recv_time_stamp(
+    reader: &mut (impl BufRead + ?Sized),
+    buffer: &mut Vec<u8>,
+    cmd: &str,
+) -> Result<u32, UrgError> {
+    let n = recv_data(reader, buffer, cmd)?;
+    if n != 6 {
+        return Err(UrgError::InvalidTimeStamp {
+            cmd: cmd.to_string(),
+            line: BString::new(buffer[..n].to_vec()),
+        });
+    }
+    verify_checksum(&buffer[..5], cmd, 2)?;
+    Ok(decode(&buffer[..4]))
+}
+
+// Reads the time stamp and data lines of a scan, decoding the data as the lines arrive. Values
+// split across two lines are carried over.
+fn recv_scan_into(
+    reader: &mut (impl BufRead + ?Sized),
+    buffer: &mut Vec<u8>,
+    cmd: &str,
+    encoding_len: usize,
+    has_intensity: bool,
+    payload: &mut UrgPayload,
+) -> Result<(), UrgError> {
+    payload.time_stamp = recv_time_stamp(reader, buffer, cmd)?;
+    payload.distance.clear();
+    payload.intensity.clear();
+
+    let value_len = if has_intensity {
+        encoding_len * 2
+    } else {
+        encoding_len
+    };
+    let mut push = |value: &[u8]| {
+        payload.distance.push(decode(&value[..encoding_len]));
+        if has_intensity {
+            payload.intensity.push(decode(&value[encoding_len..]));
+        }
+    };
+    let mut carry = [0; 8];
+    let mut carry_len = 0;
+    let mut line_index = 3;
+    loop {
+        let n = recv_data(reader, buffer, cmd)?;
+        if n == 1 {
+            break;
+        } else if n < 3 {
+            return Err(UrgError::MalformedLine {
+                cmd: cmd.to_string(),
+                line_index,
+                line: BString::new(buffer[..n].to_vec()),
+            });
+        }
+        verify_checksum(&buffer[..n - 1], cmd, line_index)?;
+        let mut data = &buffer[..n - 2];
+        if carry_len > 0 {
+            let len = (value_len - carry_len).min(data.len());
+            carry[carry_len..carry_len + len].copy_from_slice(&data[..len]);
+            carry_len += len;
+            data = &data[len..];
+            if carry_len == value_len {
+                push(&carry[..value_len]);
+                carry_len = 0;
+            }
+        }
+        let mut values = data.chunks_exact(value_len);
+        for value in &mut values {
+            push(value);
+        }
+        let rest = values.remainder();
+        carry[carry_len..carry_len + rest.len()].copy_from_slice(rest);
+        carry_len += rest.len();
+        line_index += 1;
+    }
+    Ok(())
+}
+
 #[inline]
-fn recv_data(reader: &mut impl BufRead, buffer: &mut Vec<u8>) -> io::Result<usize> {
+fn recv_data(
+    reader: &mut (impl BufRead + ?Sized),
+    buffer: &mut Vec<u8>,
+    cmd: &str,
+) -> Result<usize, UrgError> {
     buffer.clear();
-    reader.read_until(b'\n', buffer)
+    let n = match reader.read_until(b'\n', buffer) {
+        Ok(n) => n,
+        // An expired read timeout is `WouldBlock` on Unix and `TimedOut` elsewhere.
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            return Err(UrgError::Timeout {
+                cmd: cmd.to_string(),
+            })
+        }
+        Err(err) => return Err(err.into()),
+    };
+    if n == 0 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(n)
+}
+
+// Reads `KEY:value;` lines up to the blank line that ends the response.
+fn recv_fields(
+    reader: &mut (impl BufRead + ?Sized),
+    buffer: &mut Vec<u8>,
+    cmd: &str,
+) -> Result<BTreeMap<String, BString>, UrgError> {
+    let mut fields = BTreeMap::new();
+    let mut line_index = 2;
+    loop {
+        let n = recv_data(reader, buffer, cmd)?;
+        if n == 1 {
+            break;
+        }
+        let (key, value) = parse_field(&buffer[..n], cmd, line_index)?;
+        fields.insert(key, value);
+        line_index += 1;
+    }
+    Ok(fields)
+}
+
+fn parse_field(line: &[u8], cmd: &str, line_index: usize) -> Result<(String, BString), UrgError> {
+    let malformed = || UrgError::MalformedLine {
+        cmd: cmd.to_string(),
+        line_index,
+        line: BString::from(line),
+    };
+    if line.len() < 3 {
+        return Err(malformed());
+    }
+    verify_field_checksum(&line[..line.len() - 1], cmd, line_index)?;
+    let data = &line[..line.len() - 2];
+    let data = data.strip_suffix(b";").unwrap_or(data);
+    let (key, value) = data.split_once_str(":").ok_or_else(malformed)?;
+    Ok((key.to_str_lossy().into_owned(), BString::from(value)))
+}
+
+fn get_field(
+    fields: &BTreeMap<String, BString>,
+    cmd: &str,
+    key: &str,
+) -> Result<BString, UrgError> {
+    fields
+        .get(key)
+        .cloned()
+        .ok_or_else(|| UrgError::MissingField {
+            cmd: cmd.to_string(),
+            key: key.to_string(),
+        })
+}
+
+fn get_field_u32(
+    fields: &BTreeMap<String, BString>,
+    cmd: &str,
+    key: &str,
+) -> Result<u32, UrgError> {
+    let value = get_field(fields, cmd, key)?;
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or_else(|| UrgError::InvalidField {
+            cmd: cmd.to_string(),
+            key: key.to_string(),
+            value,
+        })
+}
+
+// A unique string tag for each command. The first half identifies this process run, so echoes
+// left over from a previous session never match.
+fn next_tag() -> String {
+    static SESSION: OnceLock<u32> = OnceLock::new();
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    let session = SESSION.get_or_init(|| {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| {
+                elapsed.subsec_nanos() ^ elapsed.as_secs() as u32
+            });
+        nanos ^ process::id().rotate_left(16)
+    });
+    let tag = format!(
+        "{session:08x}{:08x}",
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    debug_assert!(tag.len() <= MAX_TAG_LEN);
+    tag
+}
+
+// With a `tag`, responses echoing another tag (or none) belong to other commands and are skipped.
+fn check_echo(
+    reader: &mut (impl BufRead + ?Sized),
+    buffer: &mut Vec<u8>,
+    cmd: &str,
+    tag: Option<&str>,
+    frames: Option<&Mutex<FrameQueue>>,
+) -> Result<(), UrgError> {
+    loop {
+        let n = recv_data(reader, buffer, cmd)?;
+        let echo = &buffer[..n - 1];
+        let echo_cmd = match tag {
+            Some(tag) => match echo.rsplit_once_str(";") {
+                Some((echo_cmd, echo_tag)) if echo_tag == tag.as_bytes() => echo_cmd,
+                _ => {
+                    if !echo.is_empty() {
+                        skip_response(reader, buffer, cmd, frames)?;
+                    }
+                    continue;
+                }
+            },
+            None => echo,
+        };
+        if echo_cmd != cmd.as_bytes() {
+            return Err(UrgError::EchoMismatch {
+                cmd: cmd.to_string(),
+                received: BString::from(echo),
+            });
+        }
+        return Ok(());
+    }
+}
+
+// Reads the rest of a response whose echo is in `buffer`. Frames of a running stream are queued
+// for its iterator; anything else is stale and dropped.
+fn skip_response(
+    reader: &mut (impl BufRead + ?Sized),
+    buffer: &mut Vec<u8>,
+    cmd: &str,
+    frames: Option<&Mutex<FrameQueue>>,
+) -> Result<(), UrgError> {
+    let tag = buffer
+        .trim_end()
+        .rsplit_once_str(";")
+        .map(|(_, tag)| tag.to_str_lossy().into_owned());
+    let mut raw = buffer.clone();
+    loop {
+        let n = recv_data(reader, buffer, cmd)?;
+        raw.extend_from_slice(&buffer[..n]);
+        if n == 1 {
+            break;
+        }
+    }
+    if let (Some(frames), Some(tag)) = (frames, tag) {
+        lock(frames).push(tag, raw);
+    }
+    Ok(())
+}
+
+// Returns the status, which stays in `buffer`.
+fn recv_status<'a>(
+    reader: &mut (impl BufRead + ?Sized),
+    buffer: &'a mut Vec<u8>,
+    cmd: &str,
+) -> Result<&'a [u8], UrgError> {
+    let n = recv_data(reader, buffer, cmd)?;
+    if n < 3 {
+        return Err(UrgError::MalformedLine {
+            cmd: cmd.to_string(),
+            line_index: 1,
+            line: BString::new(buffer[..n].to_vec()),
+        });
+    }
+    verify_checksum(&buffer[..n - 1], cmd, 1)?;
+    Ok(&buffer[..n - 2])
 }
 
 fn check_send_cmd_response(
-    reader: &mut impl BufRead,
+    reader: &mut (impl BufRead + ?Sized),
     buffer: &mut Vec<u8>,
     cmd: &str,
+    tag: Option<&str>,
+    frames: Option<&Mutex<FrameQueue>>,
     ok_status: &str,
-) -> io::Result<()> {
-    let n = recv_data(reader, buffer)?;
-    if &buffer[..n - 1] != cmd.as_bytes() {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            format!(
-                "check cmd response: {} failed. recv {} != {}",
-                cmd,
-                &buffer[..n - 1].as_bstr(),
-                cmd
-            ),
-        ));
-    }
-    let n = recv_data(reader, buffer)?;
-    if &buffer[..n - 2] != ok_status.as_bytes() {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            format!(
-                "check cmd response: {} failed, status error {} != {}",
-                cmd,
-                ok_status,
-                &buffer[..n - 2].as_bstr()
-            ),
-        ));
+) -> Result<(), UrgError> {
+    check_echo(reader, buffer, cmd, tag, frames)?;
+    let status = recv_status(reader, buffer, cmd)?;
+    if status != ok_status.as_bytes() {
+        let raw = BString::from(status);
+        // Skip the rest of the response so that the next command starts on a fresh one.
+        while recv_data(reader, buffer, cmd)? > 1 {}
+        return Err(UrgError::Status {
+            cmd: cmd.to_string(),
+            status: ScipStatus::new(cmd, &raw),
+            raw,
+        });
     }
     Ok(())
 }
 
 #[cfg(test)]
 mod test {
-    use crate::decode;
+    use crate::{
+        check_send_cmd_response, checksum, decode, decode_multi_echo, get_raw_data, recv_scan_into,
+        verify_checksum, verify_field_checksum, CommunicationSpeed, FrameQueue, InterfaceType,
+        LaserStatus, MeasurementMode, MotorSpeed, OverflowPolicy, ReconnectPolicy, ScanDirection,
+        ScanStream, ScipProtocol, ScipStatus, SensorStatus, Transport, Urg, UrgError, UrgPayload,
+        UrgTimeouts, MAX_QUEUED_FRAMES,
+    };
+    use std::{
+        collections::{HashMap, VecDeque},
+        fmt,
+        io::{self, Read, Write},
+        net::{SocketAddr, TcpListener},
+        sync::{
+            mpsc::{self, RecvTimeoutError, TryRecvError},
+            Arc, Mutex,
+        },
+        thread,
+        time::Duration,
+    };
+
+    struct MockSensor {
+        handler: Box<dyn FnMut(&str) -> String + Send>,
+        input: Vec<u8>,
+        output: VecDeque<u8>,
+        // The last string tag received for each command, echoed by all its responses.
+        tags: HashMap<String, String>,
+        // Every read timeout set on the transport.
+        read_timeouts: Arc<Mutex<Vec<Option<Duration>>>>,
+    }
+
+    impl MockSensor {
+        fn new(handler: impl FnMut(&str) -> String + Send + 'static) -> Self {
+            Self {
+                handler: Box::new(handler),
+                input: Vec::new(),
+                output: VecDeque::new(),
+                tags: HashMap::new(),
+                read_timeouts: Arc::default(),
+            }
+        }
+    }
+
+    impl fmt::Debug for MockSensor {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("MockSensor").finish_non_exhaustive()
+        }
+    }
+
+    impl Read for MockSensor {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.output.len().min(buf.len());
+            for (dst, src) in buf.iter_mut().zip(self.output.drain(..n)) {
+                *dst = src;
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for MockSensor {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.input.extend_from_slice(buf);
+            while let Some(pos) = self.input.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = self.input.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line[..pos]).to_string();
+                let res = match line.split_once(';') {
+                    Some((cmd, tag)) => {
+                        self.tags.insert(cmd[..2].to_string(), tag.to_string());
+                        tag_echoes(&(self.handler)(cmd), &self.tags)
+                    }
+                    None => (self.handler)(&line),
+                };
+                self.output.extend(res.as_bytes());
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for MockSensor {
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            self.read_timeouts.lock().unwrap().push(timeout);
+            Ok(())
+        }
+    }
+
+    // Appends the string tag of its command to the echo line of every response in `res`.
+    fn tag_echoes(res: &str, tags: &HashMap<String, String>) -> String {
+        let mut tagged = String::new();
+        let mut is_echo = true;
+        for line in res.split_inclusive('\n') {
+            let echo = line.trim_end();
+            if is_echo {
+                tagged += &format!("{echo};{}\n", tags[&echo[..2]]);
+            } else {
+                tagged += line;
+            }
+            is_echo = line == "\n";
+        }
+        tagged
+    }
+
+    // Per the specification, the checksum of a `KEY:value;` line excludes the trailing ';'.
+    fn line(data: &str) -> String {
+        let sum = checksum(data.strip_suffix(';').unwrap_or(data).as_bytes());
+        format!("{data}{}\n", sum as char)
+    }
+
+    pub(crate) fn response(cmd: &str, status: &str, lines: &[&str]) -> String {
+        let mut res = format!("{cmd}\n{}", line(status));
+        for data in lines {
+            res += &line(data);
+        }
+        res + "\n"
+    }
+
+    fn utm_30lx(cmd: &str) -> String {
+        match cmd {
+            "VV" => response(
+                cmd,
+                "00",
+                &[
+                    "VEND:Hokuyo Automatic Co.,Ltd.;",
+                    "PROD:SOKUIKI Sensor TOP-URG UTM-30LX;",
+                    "FIRM:1.20.00(17/Mar./2010);",
+                    "PROT:SCIP 2.0;",
+                    "SERI:H0000000;",
+                ],
+            ),
+            "PP" => response(
+                cmd,
+                "00",
+                &[
+                    "MODL:UTM-30LX;",
+                    "DMIN:23;",
+                    "DMAX:60000;",
+                    "ARES:1440;",
+                    "AMIN:0;",
+                    "AMAX:1080;",
+                    "AFRT:540;",
+                    "SCAN:2400;",
+                ],
+            ),
+            "GD0000000200" => response(cmd, "00", &["1Dh0", "1Dh1Dh1Dh"]),
+            "GD0000200000" => response(cmd, "04", &[]),
+            "II" => utm_30lx_status(2400, false),
+            "CR00" => response(cmd, "03", &[]),
+            "HS1" => response(cmd, "03", &[]),
+            "TM0" | "TM2" => response(cmd, "00", &[]),
+            "TM1" => response(cmd, "00", &["1Dh0"]),
+            "GS0000000200" => response(cmd, "00", &["1Dh0", "1D1D1D"]),
+            // The acknowledgement and both frames arrive in a single read.
+            "MS0000000200002" => {
+                response(cmd, "00", &[])
+                    + &response("MS0000000200001", "99", &["1Dh0", "1D1D1D"])
+                    + &response("MS0000000200000", "99", &["1Dh1", "1E1E1E"])
+            }
+            _ => response(cmd, "0E", &[]),
+        }
+    }
+
+    fn utm_30lx_status(scan_speed_rpm: u32, high_sensitivity: bool) -> String {
+        let mode = if high_sensitivity {
+            "High Sensitive"
+        } else {
+            "Normal"
+        };
+        response(
+            "II",
+            "00",
+            &[
+                "MODL:UTM-30LX;",
+                "LASR:OFF;",
+                &format!("SCSP:{scan_speed_rpm};"),
+                &format!("MESM:Measuring by {mode} Mode;"),
+                "SBPS:Ethernet 100 [Mbps];",
+                "TIME:1Dh0;",
+                "STAT:Sensor works well.;",
+            ],
+        )
+    }
 
     #[test]
     fn decode_test() {
@@ -512,4 +2291,1175 @@ mod test {
         let res = decode(&[0x31, 0x44, 0x68]);
         assert_eq!(res, 5432);
     }
+
+    #[test]
+    fn decode_multi_echo_test() {
+        let (distance, intensity) = decode_multi_echo(b"1Dh&1Di1Dj&1Dk&1Dl", false);
+        assert_eq!(distance, vec![vec![5432, 5433], vec![5434, 5435, 5436]]);
+        assert!(intensity.is_empty());
+
+        let (distance, intensity) = decode_multi_echo(b"1Dh001&1Di002", true);
+        assert_eq!(distance, vec![vec![5432, 5433]]);
+        assert_eq!(intensity, vec![vec![1, 2]]);
+    }
+
+    #[test]
+    fn checksum_test() {
+        assert_eq!(checksum(b"00"), b'P');
+        assert!(verify_checksum(b"00P", "GD0000108000", 1).is_ok());
+        // The sum of a `KEY:value;` line excludes the ';', as in the example of the specification.
+        assert!(verify_field_checksum(b"VEND:Hokuyo Automatic Co.,Ltd.;[", "VV", 2).is_ok());
+        assert!(verify_field_checksum(b"MODL:UTM-30LX;@", "II", 2).is_ok());
+        // A sum that includes the ';' is a checksum error.
+        assert!(matches!(
+            verify_field_checksum(b"VEND:Hokuyo Automatic Co.,Ltd.;V", "VV", 2),
+            Err(UrgError::Checksum {
+                expected: b'[',
+                actual: b'V',
+                ..
+            })
+        ));
+        assert!(verify_field_checksum(b"MODL:UTM-30LX;;", "II", 2).is_err());
+        // Data lines are summed as they are, ';' included.
+        assert!(verify_checksum(b"VEND:Hokuyo Automatic Co.,Ltd.;V", "VV", 2).is_ok());
+        assert!(verify_checksum(b"VEND:Hokuyo Automatic Co.,Ltd.;[", "VV", 2).is_err());
+
+        match verify_checksum(b"00Q", "GD0000108000", 1) {
+            Err(UrgError::Checksum {
+                cmd,
+                line_index,
+                expected,
+                actual,
+                ..
+            }) => {
+                assert_eq!(cmd, "GD0000108000");
+                assert_eq!(line_index, 1);
+                assert_eq!(expected, b'P');
+                assert_eq!(actual, b'Q');
+            }
+            res => panic!("unexpected result {res:?}"),
+        }
+    }
+
+    #[test]
+    fn scip_status_test() {
+        assert_eq!(ScipStatus::new("GD0000108000", b"00"), ScipStatus::Ok);
+        assert_eq!(
+            ScipStatus::new("GD0000200000", b"04"),
+            ScipStatus::EndStepOutOfRange
+        );
+        assert_eq!(ScipStatus::new("BM", b"02"), ScipStatus::LaserAlreadyOn);
+        assert_eq!(ScipStatus::new("RB", b"01"), ScipStatus::RebootPending);
+        assert_eq!(
+            ScipStatus::new("MD0000108000000", b"25"),
+            ScipStatus::ProcessingStopped(25)
+        );
+        assert_eq!(
+            ScipStatus::new("GD0000108000", b"55"),
+            ScipStatus::HardwareTrouble(55)
+        );
+        assert_eq!(ScipStatus::new("XX", b"0E"), ScipStatus::UndefinedCommand);
+        assert_eq!(
+            ScipStatus::new("QT", b"05"),
+            ScipStatus::Unknown("05".into())
+        );
+        assert_eq!(
+            ScipStatus::EndStepOutOfRange.to_string(),
+            "end step exceeds sensor range"
+        );
+    }
+
+    #[test]
+    fn recv_scan_into_test() {
+        let mut buffer = Vec::new();
+        let mut payload = UrgPayload {
+            distance: vec![0; 1081],
+            intensity: vec![0; 1081],
+            ..Default::default()
+        };
+        // The second value starts on the first line and ends on the second.
+        let mut reader: &[u8] = b"1Dh0=\n1Dh00m\n11Dh002P\n\n";
+        recv_scan_into(&mut reader, &mut buffer, "GE", 3, true, &mut payload).unwrap();
+        assert_eq!(payload.time_stamp, decode(b"1Dh0"));
+        assert_eq!(payload.distance, vec![5432, 5432]);
+        assert_eq!(payload.intensity, vec![1, 2]);
+        assert!(payload.distance.capacity() >= 1081);
+    }
+
+    #[test]
+    fn stale_response_test() {
+        let mut buffer = Vec::new();
+        let mut reader: &[u8] = b"MD0000108000001;0000000000000001\n99b\n1Dh0=\n\nVV\n00P\n\nVV;0000000000000002\n00P\nVEND:x;?\n\n";
+        check_send_cmd_response(
+            &mut reader,
+            &mut buffer,
+            "VV",
+            Some("0000000000000002"),
+            None,
+            "00",
+        )
+        .unwrap();
+        assert_eq!(reader, b"VEND:x;?\n\n");
+
+        let mut reader: &[u8] = b"VV;0000000000000002\n00P\n\n";
+        match check_send_cmd_response(
+            &mut reader,
+            &mut buffer,
+            "II",
+            Some("0000000000000002"),
+            None,
+            "00",
+        ) {
+            Err(UrgError::EchoMismatch { cmd, received }) => {
+                assert_eq!(cmd, "II");
+                assert_eq!(received, "VV;0000000000000002");
+            }
+            res => panic!("unexpected result {res:?}"),
+        }
+    }
+
+    #[test]
+    fn frame_queue_test() {
+        let mut frames = FrameQueue::default();
+        frames.add_stream("a".to_string(), false);
+        frames.add_stream("b".to_string(), false);
+        frames.add_stream("d".to_string(), true);
+        for i in 0..MAX_QUEUED_FRAMES + 2 {
+            frames.push("a".to_string(), vec![i as u8]);
+            frames.push("d".to_string(), vec![i as u8]);
+        }
+        frames.push("b".to_string(), vec![0]);
+        frames.push("c".to_string(), vec![0]);
+        assert_eq!(frames.frames.len(), 2 * MAX_QUEUED_FRAMES + 3);
+        // The oldest frames of the endless stream were dropped, none of the counted one.
+        assert_eq!(frames.pop("a"), Some(vec![2]));
+        assert_eq!(frames.pop("d"), Some(vec![0]));
+        assert_eq!(frames.pop("b"), Some(vec![0]));
+        assert_eq!(frames.pop("c"), None);
+    }
+
+    #[test]
+    fn get_raw_data_checksum_test() {
+        let mut buffer = Vec::new();
+        let mut reader: &[u8] = b"1Dh0=\n1Dh1Dhj\n\n";
+        let (time_stamp, raw_data) = get_raw_data(&mut reader, &mut buffer, "GD").unwrap();
+        assert_eq!(time_stamp, decode(b"1Dh0"));
+        assert_eq!(raw_data, b"1Dh1Dh");
+
+        let mut reader: &[u8] = b"1Dh0=\n1Dh1Dij\n\n";
+        match get_raw_data(&mut reader, &mut buffer, "GD") {
+            Err(UrgError::Checksum { line_index, .. }) => assert_eq!(line_index, 3),
+            res => panic!("unexpected result {res:?}"),
+        }
+
+        // ';' is an encoded character in a data line, so the sum without it must not pass.
+        let mut reader: &[u8] = b"1Dh0=\n0;0\n\n";
+        match get_raw_data(&mut reader, &mut buffer, "GD") {
+            Err(UrgError::Checksum {
+                line_index,
+                expected,
+                actual,
+                ..
+            }) => {
+                assert_eq!(line_index, 3);
+                assert_eq!(expected, b'[');
+                assert_eq!(actual, b'0');
+            }
+            res => panic!("unexpected result {res:?}"),
+        }
+
+        // A data line needs at least one byte besides its checksum.
+        let mut reader: &[u8] = b"1Dh0=\n0\n\n";
+        match get_raw_data(&mut reader, &mut buffer, "GD") {
+            Err(UrgError::MalformedLine { line_index, .. }) => assert_eq!(line_index, 3),
+            res => panic!("unexpected result {res:?}"),
+        }
+    }
+
+    #[test]
+    fn mock_transport_test() {
+        let mut urg = Urg::from_transport(MockSensor::new(utm_30lx)).unwrap();
+        let version = urg.get_version_info().unwrap();
+        assert_eq!(version.serial_number, "H0000000");
+        assert_eq!(version.negotiated_protocol, ScipProtocol::Scip2);
+
+        let params = urg.get_sensor_params().unwrap();
+        assert_eq!(params.max_distance_mm, 60000);
+        assert_eq!(params.end_step, 1080);
+
+        assert_eq!(urg.scan_period().unwrap(), Duration::from_millis(25));
+        urg.set_motor_speed(MotorSpeed::Default).unwrap();
+        assert!(matches!(
+            urg.set_motor_speed(MotorSpeed::Level(11)),
+            Err(UrgError::InvalidArgument(_))
+        ));
+
+        assert!(!urg.get_status_info().unwrap().is_high_sensitivity());
+        match urg.set_high_sensitivity(true) {
+            Err(UrgError::Status { status, .. }) => {
+                assert_eq!(status, ScipStatus::NotSupportedByModel)
+            }
+            res => panic!("unexpected result {res:?}"),
+        }
+
+        let time_sync = urg.sync_time(3).unwrap();
+        assert_eq!(time_sync.sample_count, 3);
+
+        let payload = urg.get_distance(0, 2, 0).unwrap();
+        assert_eq!(payload.time_stamp, decode(b"1Dh0"));
+        assert_eq!(payload.distance, vec![5432; 3]);
+
+        let payload = urg.get_distance_short(0, 2, 0).unwrap();
+        assert_eq!(payload.distance, vec![decode(b"1D"); 3]);
+
+        let payloads: Vec<_> = urg
+            .get_distance_short_multi(0, 2, 0, 0, 2)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[0].time_stamp, decode(b"1Dh0"));
+        assert_eq!(payloads[0].distance, vec![decode(b"1D"); 3]);
+        assert_eq!(payloads[1].time_stamp, decode(b"1Dh1"));
+        assert_eq!(payloads[1].distance, vec![decode(b"1E"); 3]);
+
+        // Steps the model does not have are refused before sending.
+        assert!(matches!(
+            urg.get_distance(0, 2000, 0),
+            Err(UrgError::InvalidArgument(_))
+        ));
+        let res = urg.send_raw("GD0000200000").unwrap();
+        assert_eq!(res.status, ScipStatus::EndStepOutOfRange);
+    }
+
+    #[test]
+    fn motor_speed_test() {
+        // `II` reports the speed set by the last `CR`.
+        let mut scan_speed_rpm = 2400;
+        let mut urg = Urg::from_transport(MockSensor::new(move |cmd| match cmd {
+            "CR05" => {
+                scan_speed_rpm = 1200;
+                response(cmd, "00", &[])
+            }
+            "CR00" if scan_speed_rpm == 2400 => response(cmd, "03", &[]),
+            "CR00" => {
+                scan_speed_rpm = 2400;
+                response(cmd, "00", &[])
+            }
+            "II" => utm_30lx_status(scan_speed_rpm, false),
+            "MD0000000200000" | "QT" => response(cmd, "00", &[]),
+            _ => utm_30lx(cmd),
+        }))
+        .unwrap();
+        let timeouts = urg.timeouts();
+
+        urg.set_motor_speed(MotorSpeed::Level(5)).unwrap();
+        assert_eq!(urg.get_status_info().unwrap().scanning_speed_rpm, 1200);
+        assert_eq!(urg.scan_period().unwrap(), Duration::from_millis(50));
+        let scans = urg.get_distance_multi(0, 2, 0, 0, 0).unwrap();
+        assert_eq!(
+            scans.raw.frame_timeout,
+            timeouts.frame(Duration::from_millis(50), 0)
+        );
+        scans.cancel().unwrap();
+
+        urg.set_motor_speed(MotorSpeed::Default).unwrap();
+        assert_eq!(urg.scan_period().unwrap(), Duration::from_millis(25));
+        let scans = urg.get_distance_multi(0, 2, 0, 0, 0).unwrap();
+        assert_eq!(
+            scans.raw.frame_timeout,
+            timeouts.frame(Duration::from_millis(25), 0)
+        );
+        scans.cancel().unwrap();
+    }
+
+    #[test]
+    fn high_sensitivity_test() {
+        let mut high_sensitivity = false;
+        let mut urg = Urg::from_transport(MockSensor::new(move |cmd| match cmd {
+            "HS0" | "HS1" if high_sensitivity == (cmd == "HS1") => response(cmd, "02", &[]),
+            "HS0" | "HS1" => {
+                high_sensitivity = cmd == "HS1";
+                response(cmd, "00", &[])
+            }
+            "II" => utm_30lx_status(2400, high_sensitivity),
+            _ => utm_30lx(cmd),
+        }))
+        .unwrap();
+
+        urg.set_high_sensitivity(true).unwrap();
+        let status_info = urg.get_status_info().unwrap();
+        assert_eq!(
+            status_info.measurement_mode,
+            MeasurementMode::HighSensitivity
+        );
+        assert!(status_info.is_high_sensitivity());
+        // Already in effect.
+        urg.set_high_sensitivity(true).unwrap();
+
+        urg.set_high_sensitivity(false).unwrap();
+        let status_info = urg.get_status_info().unwrap();
+        assert_eq!(status_info.measurement_mode, MeasurementMode::Normal);
+    }
+
+    #[test]
+    fn status_info_fields_test() {
+        assert_eq!(LaserStatus::new(b"ON"), LaserStatus::On);
+        assert_eq!(LaserStatus::new(b"OFF"), LaserStatus::Off);
+        assert_eq!(
+            MeasurementMode::new(b"Measuring by High Sensitive Mode"),
+            MeasurementMode::HighSensitivity
+        );
+        assert_eq!(
+            CommunicationSpeed::new(b"115200[bps]"),
+            CommunicationSpeed::BitsPerSecond(115200)
+        );
+        assert_eq!(
+            CommunicationSpeed::new(b"USB Full Speed [12Mbps]"),
+            CommunicationSpeed::BitsPerSecond(12_000_000)
+        );
+        assert_eq!(
+            SensorStatus::new(b"Stable 000 no error."),
+            SensorStatus::Normal
+        );
+        assert_eq!(
+            SensorStatus::new(b"Abnormal 051 motor error"),
+            SensorStatus::Error {
+                code: 51,
+                description: "Abnormal 051 motor error".into()
+            }
+        );
+
+        let urg = Urg::from_transport(MockSensor::new(utm_30lx)).unwrap();
+        let status = urg.get_status_info().unwrap();
+        assert_eq!(status.laser_status, LaserStatus::Off);
+        assert_eq!(status.measurement_mode, MeasurementMode::Normal);
+        assert_eq!(status.communication_speed, CommunicationSpeed::Ethernet);
+        assert_eq!(status.sensor_status, SensorStatus::Normal);
+        assert_eq!(status.fields["SBPS"], "Ethernet 100 [Mbps]");
+    }
+
+    #[test]
+    fn sensor_params_by_key_test() {
+        let sensor = MockSensor::new(|cmd| match cmd {
+            "PP" => response(
+                cmd,
+                "00",
+                &[
+                    "SCAN:2400;",
+                    "MODL:UST-10LX;",
+                    "AFRT:540;",
+                    "DMIN:20;",
+                    "DMAX:30000;",
+                    "ARES:1440;",
+                    "AMIN:0;",
+                    "AMAX:1080;",
+                    "SDIR:CW;",
+                    "XTRA:1;",
+                ],
+            ),
+            _ => utm_30lx(cmd),
+        });
+        let urg = Urg::from_transport(sensor).unwrap();
+        let params = urg.get_sensor_params().unwrap();
+        assert_eq!(params.sensor_model, "UST-10LX");
+        assert_eq!(params.front_dir_step, 540);
+        assert_eq!(params.std_scan_speed_rpm, 2400);
+        assert_eq!(params.scan_direction, ScanDirection::Clockwise);
+        assert_eq!(params.fields["XTRA"], "1");
+
+        let params = Urg::from_transport(MockSensor::new(utm_30lx))
+            .unwrap()
+            .get_sensor_params()
+            .unwrap();
+        assert_eq!(params.scan_direction, ScanDirection::CounterClockwise);
+    }
+
+    #[test]
+    fn stop_stream_on_drop_test() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let frame = |time_stamp| response("MD0000000200000", "99", &[time_stamp, "1Dh1Dh1Dh"]);
+        let sensor = MockSensor::new({
+            let received = received.clone();
+            move |cmd| {
+                received.lock().unwrap().push(cmd.to_string());
+                match cmd {
+                    "MD0000000200000" => response(cmd, "00", &[]) + &frame("1Dh0"),
+                    // Frames already on their way arrive before the answer to `QT`.
+                    "QT" => frame("1Dh1") + &frame("1Dh2") + &response(cmd, "00", &[]),
+                    _ => utm_30lx(cmd),
+                }
+            }
+        });
+        let urg = Urg::from_transport(sensor).unwrap();
+
+        let mut scans = urg.get_distance_multi(0, 2, 0, 0, 0).unwrap();
+        scans.next().unwrap().unwrap();
+        drop(scans);
+        assert_eq!(urg.get_version_info().unwrap().serial_number, "H0000000");
+        assert!(!urg.is_capturing());
+
+        let scans = urg.get_distance_multi(0, 2, 0, 0, 0).unwrap();
+        scans.cancel().unwrap();
+        let received = received.lock().unwrap();
+        assert_eq!(received.iter().filter(|cmd| *cmd == "QT").count(), 2);
+    }
+
+    #[test]
+    fn scan_stream_test() {
+        let urg = Urg::from_transport(MockSensor::new(utm_30lx)).unwrap();
+        let scans = urg.get_distance_short_multi(0, 2, 0, 0, 2).unwrap();
+        let stream = ScanStream::spawn(scans, 1, OverflowPolicy::Block);
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            stream.recv_timeout(timeout).unwrap().time_stamp,
+            decode(b"1Dh0")
+        );
+        assert_eq!(
+            stream.recv_timeout(timeout).unwrap().time_stamp,
+            decode(b"1Dh1")
+        );
+        assert!(matches!(
+            stream.recv_timeout(Duration::MAX),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+        assert_eq!(stream.latest().unwrap().time_stamp, decode(b"1Dh1"));
+        assert!(matches!(stream.try_recv(), Err(TryRecvError::Disconnected)));
+        stream.stop().unwrap();
+
+        let scans = urg.get_distance_short_multi(0, 2, 0, 0, 2).unwrap();
+        let stream = ScanStream::spawn(scans, 1, OverflowPolicy::DropOldest);
+        while stream.is_running() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(stream.dropped_count(), 1);
+        assert_eq!(stream.try_recv().unwrap().distance, vec![decode(b"1E"); 3]);
+    }
+
+    #[test]
+    fn command_during_stream_test() {
+        let frame = |time_stamp| response("MD0000000200000", "99", &[time_stamp, "1Dh1Dh1Dh"]);
+        let urg = Urg::from_transport(MockSensor::new(move |cmd| match cmd {
+            "MD0000000200000" => response(cmd, "00", &[]) + &frame("1Dh0"),
+            // The next frame is sent before the answer to `II`.
+            "II" => frame("1Dh1") + &utm_30lx(cmd),
+            _ => utm_30lx(cmd),
+        }))
+        .unwrap();
+
+        let mut scans = urg.get_distance_multi(0, 2, 0, 0, 0).unwrap();
+        assert_eq!(scans.next().unwrap().unwrap().time_stamp, decode(b"1Dh0"));
+        assert_eq!(urg.get_status_info().unwrap().scanning_speed_rpm, 2400);
+        let payload = scans.next().unwrap().unwrap();
+        assert_eq!(payload.time_stamp, decode(b"1Dh1"));
+        assert_eq!(payload.distance, vec![5432; 3]);
+    }
+
+    #[test]
+    fn multi_echo_test() {
+        let urg = Urg::from_transport(MockSensor::new(|cmd| match cmd {
+            "VV" => response(
+                cmd,
+                "00",
+                &[
+                    "VEND:Hokuyo Automatic Co.,Ltd.;",
+                    "PROD:SOKUIKI Sensor TOP-URG UTM-30LX-EW;",
+                    "FIRM:1.1.3(19/Apr./2012);",
+                    "PROT:SCIP 2.0;",
+                    "SERI:H0000000;",
+                ],
+            ),
+            "PP" => response(
+                cmd,
+                "00",
+                &[
+                    "MODL:UTM-30LX-EW;",
+                    "DMIN:23;",
+                    "DMAX:60000;",
+                    "ARES:1440;",
+                    "AMIN:0;",
+                    "AMAX:1080;",
+                    "AFRT:540;",
+                    "SCAN:2400;",
+                ],
+            ),
+            // Two echoes at the first step, one at the others.
+            "HD0000000200" => response(cmd, "00", &["1Dh0", "1Dh&1Di1Dj1Dk"]),
+            "HE0000000200" => response(cmd, "00", &["1Dh0", "1Dh001&1Di0021Dj0031Dk004"]),
+            // Echoes split across the lines of the second frame.
+            "ND0000000200002" => {
+                response(cmd, "00", &[])
+                    + &response("ND0000000200001", "99", &["1Dh0", "1Dh1Dj1Dk"])
+                    + &response("ND0000000200000", "99", &["1Dh1", "1Dh&1Di&", "1Dj1Dk1Dl"])
+            }
+            _ => utm_30lx(cmd),
+        }))
+        .unwrap();
+        assert!(urg.get_capabilities().unwrap().multi_echo);
+
+        let payload = urg.get_multi_echo_distance(0, 2, 0).unwrap();
+        assert_eq!(payload.time_stamp, decode(b"1Dh0"));
+        assert_eq!(
+            payload.distance,
+            vec![vec![5432, 5433], vec![5434], vec![5435]]
+        );
+        assert!(payload.intensity.is_empty());
+
+        let payload = urg.get_multi_echo_distance_intensity(0, 2, 0).unwrap();
+        assert_eq!(
+            payload.distance,
+            vec![vec![5432, 5433], vec![5434], vec![5435]]
+        );
+        assert_eq!(payload.intensity, vec![vec![1, 2], vec![3], vec![4]]);
+
+        let payloads: Vec<_> = urg
+            .get_multi_echo_distance_multi(0, 2, 0, 0, 2)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[0].time_stamp, decode(b"1Dh0"));
+        assert_eq!(
+            payloads[0].distance,
+            vec![vec![5432], vec![5434], vec![5435]]
+        );
+        assert_eq!(payloads[1].time_stamp, decode(b"1Dh1"));
+        assert_eq!(
+            payloads[1].distance,
+            vec![vec![5432, 5433, 5434], vec![5435], vec![5436]]
+        );
+    }
+
+    #[test]
+    fn frame_timeout_set_once_test() {
+        let frame = |left, time_stamp| {
+            response(
+                &format!("MD00000002000{left}"),
+                "99",
+                &[time_stamp, "1Dh1Dh1Dh"],
+            )
+        };
+        let sensor = MockSensor::new(move |cmd| match cmd {
+            "MD0000000200004" => {
+                response(cmd, "00", &[]) + &frame("03", "1Dh0") + &frame("02", "1Dh1")
+            }
+            // The last frames follow the answer to `II`.
+            "II" => utm_30lx(cmd) + &frame("01", "1Dh2") + &frame("00", "1Dh3"),
+            _ => utm_30lx(cmd),
+        });
+        let read_timeouts = sensor.read_timeouts.clone();
+        let urg = Urg::from_transport(sensor).unwrap();
+
+        let mut scans = urg.get_distance_multi(0, 2, 0, 0, 4).unwrap();
+        scans.next().unwrap().unwrap();
+        scans.next().unwrap().unwrap();
+        urg.get_status_info().unwrap();
+        assert_eq!(scans.map(Result::unwrap).count(), 2);
+
+        // Set at open, for the first frame, for `II` and for the frame after it.
+        let read_timeouts = read_timeouts.lock().unwrap();
+        let response = UrgTimeouts::default().response;
+        assert_eq!(read_timeouts.len(), 4);
+        assert_eq!(read_timeouts[0], response);
+        assert_ne!(read_timeouts[1], response);
+        assert_eq!(read_timeouts[2], response);
+        assert_eq!(read_timeouts[3], read_timeouts[1]);
+    }
+
+    #[test]
+    fn counted_stream_overflow_test() {
+        let frame = |left: u32| {
+            response(
+                &format!("MD00000002000{left:0>2}"),
+                "99",
+                &["1Dh0", "1Dh1Dh1Dh"],
+            )
+        };
+        let urg = Urg::from_transport(MockSensor::new(move |cmd| match cmd {
+            "MD0000000200070" => response(cmd, "00", &[]) + &frame(69),
+            // More frames than an endless stream keeps arrive before the answer to `II`.
+            "II" => (0..69).rev().map(frame).collect::<String>() + &utm_30lx(cmd),
+            _ => utm_30lx(cmd),
+        }))
+        .unwrap();
+
+        let mut scans = urg.get_distance_multi(0, 2, 0, 0, 70).unwrap();
+        scans.next().unwrap().unwrap();
+        urg.get_status_info().unwrap();
+        const { assert!(69 > MAX_QUEUED_FRAMES) };
+        assert_eq!(scans.map(Result::unwrap).count(), 69);
+    }
+
+    #[test]
+    fn buffered_frames_test() {
+        let frame = |time_stamp, left| {
+            response(
+                &format!("MD00000002000{left}"),
+                "99",
+                &[time_stamp, "1Dh1Dh1Dh"],
+            )
+        };
+        // The acknowledgement and every frame arrive in a single read.
+        let urg = Urg::from_transport(MockSensor::new(move |cmd| match cmd {
+            "MD0000000200002" => {
+                response(cmd, "00", &[]) + &frame("1Dh0", "01") + &frame("1Dh1", "00")
+            }
+            _ => utm_30lx(cmd),
+        }))
+        .unwrap();
+
+        let payloads: Vec<_> = urg
+            .get_distance_multi(0, 2, 0, 0, 2)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[0].time_stamp, decode(b"1Dh0"));
+        assert_eq!(payloads[1].time_stamp, decode(b"1Dh1"));
+        assert_eq!(urg.get_status_info().unwrap().scanning_speed_rpm, 2400);
+    }
+
+    #[test]
+    fn send_raw_test() {
+        let urg = Urg::from_transport(MockSensor::new(utm_30lx)).unwrap();
+        let res = urg.send_raw("GD0000000200").unwrap();
+        assert_eq!(res.status, ScipStatus::Ok);
+        assert_eq!(res.lines.len(), 2);
+        assert_eq!(res.lines[0].decode(4), Some(vec![decode(b"1Dh0")]));
+        assert_eq!(res.lines[1].data, "1Dh1Dh1Dh");
+        assert_eq!(res.lines[1].raw, "1Dh1Dh1DhG");
+        assert_eq!(res.lines[1].decode(3), Some(vec![5432; 3]));
+
+        let res = urg.send_raw("VV").unwrap();
+        assert_eq!(res.lines[0].data, "VEND:Hokuyo Automatic Co.,Ltd.;");
+        assert_eq!(res.lines[0].decode(3), None);
+
+        let res = urg.send_raw("XX").unwrap();
+        assert_eq!(res.status, ScipStatus::UndefinedCommand);
+        assert_eq!(res.raw_status, "0E");
+        assert!(res.lines.is_empty());
+
+        assert!(matches!(
+            urg.send_raw("VV;tag"),
+            Err(UrgError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn capabilities_test() {
+        let urg = Urg::from_transport(MockSensor::new(|cmd| match cmd {
+            "PP" => response(
+                cmd,
+                "00",
+                &[
+                    "MODL:URG-04LX;",
+                    "DMIN:20;",
+                    "DMAX:5600;",
+                    "ARES:1024;",
+                    "AMIN:44;",
+                    "AMAX:725;",
+                    "AFRT:384;",
+                    "SCAN:600;",
+                ],
+            ),
+            _ => utm_30lx(cmd),
+        }))
+        .unwrap();
+        let capabilities = urg.get_capabilities().unwrap();
+        assert!(!capabilities.intensity);
+        assert_eq!(capabilities.max_step, 725);
+        assert_eq!(capabilities.interface, InterfaceType::Other);
+        match urg.get_distance_intensity(44, 725, 0) {
+            Err(UrgError::Unsupported { cmd, sensor_model }) => {
+                assert_eq!(cmd, "GE0044072500");
+                assert_eq!(sensor_model, "URG-04LX");
+            }
+            res => panic!("unexpected result {res:?}"),
+        }
+
+        // Unknown models are probed; the mock only knows `GS`.
+        let urg = Urg::from_transport(MockSensor::new(|cmd| match cmd {
+            "VV" => response(
+                cmd,
+                "00",
+                &[
+                    "VEND:Example;",
+                    "PROD:XYZ-00LX;",
+                    "FIRM:1.0;",
+                    "PROT:SCIP 2.0;",
+                    "SERI:0;",
+                ],
+            ),
+            "PP" => response(
+                cmd,
+                "00",
+                &[
+                    "MODL:XYZ-00LX;",
+                    "DMIN:23;",
+                    "DMAX:60000;",
+                    "ARES:1440;",
+                    "AMIN:0;",
+                    "AMAX:1080;",
+                    "AFRT:540;",
+                    "SCAN:2400;",
+                ],
+            ),
+            "GS0540054000" => response(cmd, "10", &[]),
+            _ => utm_30lx(cmd),
+        }))
+        .unwrap();
+        let capabilities = urg.get_capabilities().unwrap();
+        assert!(!capabilities.intensity);
+        assert!(!capabilities.multi_echo);
+        assert!(capabilities.short_encoding);
+    }
+
+    #[test]
+    fn scip1_switch_test() {
+        let mut is_scip2 = false;
+        let sensor = MockSensor::new(move |cmd| match cmd {
+            _ if is_scip2 => utm_30lx(cmd),
+            "SCIP2.0" => {
+                is_scip2 = true;
+                format!("{cmd}\n0\n\n")
+            }
+            _ => format!("{cmd}\nE\n\n"),
+        });
+        let urg = Urg::from_transport(sensor).unwrap();
+        let version = urg.get_version_info().unwrap();
+        assert_eq!(
+            version.negotiated_protocol,
+            ScipProtocol::Scip1SwitchedToScip2
+        );
+    }
+
+    #[test]
+    fn stale_stream_at_open_test() {
+        // A frame of a stream started by an earlier session, which never stopped it.
+        let stale_frame = response("MD0000000200000", "99", &["1Dh0", "1Dh1Dh1Dh"]).replacen(
+            '\n',
+            ";deadbeef00000000\n",
+            1,
+        );
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut sensor = MockSensor::new({
+            let received = received.clone();
+            let stale_frame = stale_frame.clone();
+            move |cmd| {
+                received.lock().unwrap().push(cmd.to_string());
+                match cmd {
+                    "VV" if received.lock().unwrap().len() == 1 => {
+                        utm_30lx(cmd) + &stale_frame + &stale_frame
+                    }
+                    "QT" => response(cmd, "00", &[]),
+                    _ => utm_30lx(cmd),
+                }
+            }
+        });
+        sensor.output.extend(stale_frame.as_bytes());
+
+        let urg = Urg::from_transport(sensor).unwrap();
+        assert_eq!(urg.get_version_info().unwrap().serial_number, "H0000000");
+        assert_eq!(*received.lock().unwrap(), ["VV", "QT", "VV", "VV"]);
+    }
+
+    // Serves one TCP connection with `handler` as the sensor.
+    fn tcp_mock_sensor(
+        handler: impl FnMut(&str) -> String + Send + 'static,
+    ) -> (SocketAddr, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let sensor = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut sensor = MockSensor::new(handler);
+            let mut buf = [0; 256];
+            let mut res = Vec::new();
+            while let Ok(n @ 1..) = stream.read(&mut buf) {
+                sensor.write_all(&buf[..n]).unwrap();
+                res.clear();
+                sensor.read_to_end(&mut res).unwrap();
+                stream.write_all(&res).unwrap();
+            }
+        });
+        (address, sensor)
+    }
+
+    #[test]
+    fn timeout_test() {
+        let (address, sensor) = tcp_mock_sensor(|cmd| match cmd {
+            // The sensor never answers.
+            "GD0000000200" => String::new(),
+            // The stream starts but no frame follows.
+            "MD0000000200000" | "QT" => response(cmd, "00", &[]),
+            _ => utm_30lx(cmd),
+        });
+
+        let timeouts = UrgTimeouts {
+            response: Some(Duration::from_millis(100)),
+            ..UrgTimeouts::default()
+        };
+        let mut urg = Urg::open_with_timeouts(address.ip(), address.port(), timeouts).unwrap();
+        let zero = UrgTimeouts {
+            response: Some(Duration::ZERO),
+            ..timeouts
+        };
+        assert!(matches!(
+            urg.set_timeouts(zero),
+            Err(UrgError::InvalidArgument(_))
+        ));
+        assert_eq!(
+            timeouts.frame(Duration::from_millis(25), 1),
+            Some(Duration::from_millis(300))
+        );
+        let many_intervals = UrgTimeouts {
+            frame_intervals: u32::MAX,
+            ..timeouts
+        };
+        assert_eq!(
+            many_intervals.frame(Duration::from_secs(u64::MAX / 2), 9),
+            Some(Duration::MAX)
+        );
+        #[allow(deprecated)]
+        let (ip_address, port) = (urg.ip_address, urg.port);
+        assert_eq!(SocketAddr::new(ip_address, port), urg.peer_addr().unwrap());
+        assert!(matches!(
+            urg.get_distance(0, 2, 0),
+            Err(UrgError::Timeout { cmd }) if cmd == "GD0000000200"
+        ));
+        assert_eq!(urg.get_version_info().unwrap().serial_number, "H0000000");
+
+        let mut scans = urg.get_distance_multi(0, 2, 0, 0, 0).unwrap();
+        assert!(matches!(
+            scans.next(),
+            Some(Err(UrgError::Timeout { cmd })) if cmd == "MD0000000200000"
+        ));
+        drop(scans);
+        drop(urg);
+        sensor.join().unwrap();
+    }
+
+    #[test]
+    fn scan_stream_silent_sensor_test() {
+        let (address, sensor) = tcp_mock_sensor(|cmd| match cmd {
+            // The stream starts but no frame follows.
+            "MD0000000200000" | "QT" => response(cmd, "00", &[]),
+            _ => utm_30lx(cmd),
+        });
+
+        let timeouts = UrgTimeouts {
+            response: None,
+            ..UrgTimeouts::default()
+        };
+        let urg = Urg::open_with_timeouts(address.ip(), address.port(), timeouts).unwrap();
+        let scans = urg.get_distance_multi(0, 2, 0, 0, 0).unwrap();
+        let stream = ScanStream::spawn(scans, 1, OverflowPolicy::Block);
+        assert!(matches!(
+            stream.recv_timeout(Duration::MAX),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+        assert!(matches!(stream.stop(), Err(UrgError::Timeout { .. })));
+        drop(urg);
+        sensor.join().unwrap();
+    }
+
+    #[test]
+    fn stop_timeout_test() {
+        let frame = response("MD0000000200000", "99", &["1Dh0", "1Dh1Dh1Dh"]);
+        let (address, sensor) = tcp_mock_sensor(move |cmd| match cmd {
+            "MD0000000200000" => response(cmd, "00", &[]) + &frame,
+            // The sensor died and never answers.
+            "QT" => String::new(),
+            _ => utm_30lx(cmd),
+        });
+
+        let timeouts = UrgTimeouts {
+            response: None,
+            ..UrgTimeouts::default()
+        };
+        let urg = Urg::open_with_timeouts(address.ip(), address.port(), timeouts).unwrap();
+        let mut scans = urg.get_distance_multi(0, 2, 0, 0, 0).unwrap();
+        scans.next().unwrap().unwrap();
+        assert!(matches!(scans.cancel(), Err(UrgError::Timeout { .. })));
+        drop(urg);
+        sensor.join().unwrap();
+    }
+
+    #[test]
+    fn reconnect_unlocks_state_while_connecting_test() {
+        let stream_handler = |time_stamp: &'static str| {
+            move |cmd: &str| match cmd {
+                "MD0000000200000" => {
+                    response(cmd, "00", &[]) + &response(cmd, "99", &[time_stamp, "1Dh1Dh1Dh"])
+                }
+                "QT" => response(cmd, "00", &[]),
+                _ => utm_30lx(cmd),
+            }
+        };
+        // The first connection ends after one frame.
+        let mut urg = Urg::from_transport(MockSensor::new(stream_handler("1Dh0"))).unwrap();
+        let (connecting_tx, connecting_rx) = mpsc::channel();
+        let (sensor_tx, sensor_rx) = mpsc::channel();
+        let policy = ReconnectPolicy {
+            max_attempts: Some(1),
+            ..ReconnectPolicy::default()
+        };
+        urg.set_reconnect_policy(policy, move || {
+            _ = connecting_tx.send(());
+            sensor_rx
+                .recv()
+                .unwrap_or_else(|_| Err(io::ErrorKind::ConnectionRefused.into()))
+        });
+
+        let scans = urg.get_distance_multi(0, 2, 0, 0, 0).unwrap();
+        let stream = ScanStream::spawn(scans, 2, OverflowPolicy::Block);
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            stream.recv_timeout(timeout).unwrap().time_stamp,
+            decode(b"1Dh0")
+        );
+
+        // While `connect` blocks, the reconnect settings can still be changed.
+        connecting_rx.recv_timeout(timeout).unwrap();
+        let (done_tx, done_rx) = mpsc::channel();
+        thread::spawn(move || {
+            urg.on_reconnect(|_| {});
+            done_tx.send(urg).unwrap();
+        });
+        let on_reconnect_done = done_rx.recv_timeout(timeout);
+        // Let `connect` return before checking, so that a failure does not hang the stream.
+        sensor_tx
+            .send(Ok(MockSensor::new(stream_handler("1Dh1"))))
+            .unwrap();
+        drop(sensor_tx);
+        let urg = on_reconnect_done.unwrap();
+        assert_eq!(
+            stream.recv_timeout(timeout).unwrap().time_stamp,
+            decode(b"1Dh1")
+        );
+        // The second connection ends too, and no third one is offered.
+        assert!(matches!(stream.stop(), Err(UrgError::Io(_))));
+        drop(urg);
+    }
+
+    #[test]
+    fn reconnect_test() {
+        let frame = |count, time_stamp| {
+            response(
+                &format!("MD00000002000{count}"),
+                "99",
+                &[time_stamp, "1Dh1Dh1Dh"],
+            )
+        };
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut sensors = VecDeque::from([
+            Err(io::Error::from(io::ErrorKind::ConnectionRefused)),
+            // The connection drops after the first frame and again at `II`.
+            Ok(MockSensor::new({
+                let received = received.clone();
+                move |cmd| {
+                    received.lock().unwrap().push(cmd.to_string());
+                    match cmd {
+                        "BM" | "HS1" => response(cmd, "00", &[]),
+                        "MD0000000200002" => {
+                            response(cmd, "00", &[]) + &frame("01", "1Dh1") + &frame("00", "1Dh2")
+                        }
+                        "II" => String::new(),
+                        _ => utm_30lx(cmd),
+                    }
+                }
+            })),
+            Ok(MockSensor::new(|cmd| match cmd {
+                "BM" | "HS1" => response(cmd, "00", &[]),
+                _ => utm_30lx(cmd),
+            })),
+        ]);
+        let mut urg = Urg::from_transport(MockSensor::new(move |cmd| match cmd {
+            "BM" | "HS1" => response(cmd, "00", &[]),
+            "MD0000000200003" => response(cmd, "00", &[]) + &frame("02", "1Dh0"),
+            _ => utm_30lx(cmd),
+        }))
+        .unwrap();
+        let policy = ReconnectPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..ReconnectPolicy::default()
+        };
+        urg.set_reconnect_policy(policy, move || sensors.pop_front().unwrap());
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        urg.on_reconnect({
+            let attempts = attempts.clone();
+            move |event| {
+                assert!(matches!(event.cause, UrgError::Io(_)));
+                attempts.lock().unwrap().push(event.attempts);
+            }
+        });
+        urg.set_high_sensitivity(true).unwrap();
+        urg.start_capture().unwrap();
+
+        let time_stamps: Vec<_> = urg
+            .get_distance_multi(0, 2, 0, 0, 3)
+            .unwrap()
+            .map(|scan| scan.unwrap().time_stamp)
+            .collect();
+        assert_eq!(
+            time_stamps,
+            [decode(b"1Dh0"), decode(b"1Dh1"), decode(b"1Dh2")]
+        );
+        assert_eq!(urg.get_status_info().unwrap().scanning_speed_rpm, 2400);
+        assert_eq!(*attempts.lock().unwrap(), [2, 1]);
+        // The settings are replayed before the stream restarts for the scans left.
+        assert_eq!(
+            *received.lock().unwrap(),
+            ["VV", "HS1", "BM", "MD0000000200002", "II"]
+        );
+    }
+
+    #[test]
+    fn reconnect_cancel_test() {
+        let frame = response("MD0000000200000", "99", &["1Dh0", "1Dh1Dh1Dh"]);
+        let mut urg = Urg::from_transport(MockSensor::new(move |cmd| match cmd {
+            "MD0000000200000" => response(cmd, "00", &[]) + &frame,
+            _ => utm_30lx(cmd),
+        }))
+        .unwrap();
+        let policy = ReconnectPolicy {
+            max_attempts: None,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(10),
+        };
+        urg.set_reconnect_policy(policy, || {
+            Err::<MockSensor, _>(io::Error::from(io::ErrorKind::ConnectionRefused))
+        });
+
+        // The sensor stays away, so only stopping ends the reconnect.
+        let scans = urg.get_distance_multi(0, 2, 0, 0, 0).unwrap();
+        let stream = ScanStream::spawn(scans, 4, OverflowPolicy::DropOldest);
+        stream.recv_timeout(Duration::from_secs(5)).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(stream.is_running());
+        assert!(matches!(stream.stop(), Err(UrgError::Io(_))));
+    }
+
+    // Runs `handler` as a sensor on the other end of an in-memory pipe.
+    #[cfg(feature = "tokio")]
+    fn async_mock_sensor(
+        mut handler: impl FnMut(&str) -> String + Send + 'static,
+    ) -> tokio::io::DuplexStream {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let (transport, sensor) = tokio::io::duplex(1 << 16);
+        tokio::spawn(async move {
+            let mut sensor = BufReader::new(sensor);
+            let mut tags = HashMap::new();
+            let mut line = String::new();
+            while sensor.read_line(&mut line).await.unwrap_or(0) > 0 {
+                let res = match line.trim_end().split_once(';') {
+                    Some((cmd, tag)) => {
+                        tags.insert(cmd[..2].to_string(), tag.to_string());
+                        tag_echoes(&handler(cmd), &tags)
+                    }
+                    None => handler(line.trim_end()),
+                };
+                line.clear();
+                if sensor.write_all(res.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        transport
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_urg_test() {
+        use crate::AsyncUrg;
+        use futures_core::Stream;
+        use std::{future, pin::Pin};
+
+        async fn next<S: Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+            future::poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx)).await
+        }
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let frame = |time_stamp| response("MD0000000200000", "99", &[time_stamp, "1Dh1Dh1Dh"]);
+        let handler = {
+            let received = received.clone();
+            move |cmd: &str| {
+                received.lock().unwrap().push(cmd.to_string());
+                match cmd {
+                    "BM" => response(cmd, "00", &[]),
+                    "MD0000000200000" => response(cmd, "00", &[]) + &frame("1Dh0"),
+                    "II" => frame("1Dh1") + &utm_30lx(cmd),
+                    "QT" => frame("1Dh2") + &response(cmd, "00", &[]),
+                    _ => utm_30lx(cmd),
+                }
+            }
+        };
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut urg = AsyncUrg::from_transport(async_mock_sensor(handler))
+                .await
+                .unwrap();
+            assert_eq!(urg.get_sensor_params().await.unwrap().end_step, 1080);
+            assert_eq!(
+                urg.get_distance(0, 2, 0).await.unwrap().distance,
+                vec![5432; 3]
+            );
+            assert!(matches!(
+                urg.get_distance(0, 20, 0).await,
+                Err(UrgError::Status { .. })
+            ));
+            // Checked against the capabilities like `Urg`.
+            assert!(matches!(
+                urg.get_distance(0, 2000, 0).await,
+                Err(UrgError::InvalidArgument(_))
+            ));
+            assert!(matches!(
+                urg.get_multi_echo_distance(0, 2, 0).await,
+                Err(UrgError::Unsupported { .. })
+            ));
+            urg.start_capture().await.unwrap();
+            assert!(urg.is_capturing());
+
+            let mut scans = urg.get_distance_short_multi(0, 2, 0, 0, 2).await.unwrap();
+            let payload = next(&mut scans).await.unwrap().unwrap();
+            assert_eq!(payload.time_stamp, decode(b"1Dh0"));
+            assert_eq!(payload.distance, vec![decode(b"1D"); 3]);
+            assert_eq!(
+                next(&mut scans).await.unwrap().unwrap().distance,
+                vec![decode(b"1E"); 3]
+            );
+            assert!(next(&mut scans).await.is_none());
+
+            // A frame of the running stream arrives before the answer to `II`.
+            let mut scans = urg.get_distance_multi(0, 2, 0, 0, 0).await.unwrap();
+            assert_eq!(
+                next(&mut scans).await.unwrap().unwrap().time_stamp,
+                decode(b"1Dh0")
+            );
+            assert_eq!(
+                urg.get_status_info().await.unwrap().scanning_speed_rpm,
+                2400
+            );
+            assert_eq!(
+                next(&mut scans).await.unwrap().unwrap().time_stamp,
+                decode(b"1Dh1")
+            );
+            drop(scans);
+            while urg.is_capturing() {
+                tokio::task::yield_now().await;
+            }
+            assert_eq!(
+                urg.get_version_info().await.unwrap().serial_number,
+                "H0000000"
+            );
+
+            let scans = urg.get_distance_multi(0, 2, 0, 0, 0).await.unwrap();
+            scans.cancel().await.unwrap();
+        });
+        let received = received.lock().unwrap();
+        assert_eq!(received.iter().filter(|cmd| *cmd == "QT").count(), 2);
+    }
 }
@@ -0,0 +1,287 @@
+use crate::{
+    lock, recv_data, MotorSpeed, ScipStatus, TimeStampTracker, Transport, Urg, UrgError,
+    UrgTimeouts,
+};
+use std::{
+    fmt,
+    io::{self, BufReader},
+    net::TcpStream,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Opt-in reconnection of `Urg` after I/O errors and timeouts. The wait before each further
+/// attempt doubles from `initial_backoff` up to `max_backoff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Attempts before giving up; `None` tries forever.
+    pub max_attempts: Option<u32>,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: Some(10),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Reported once a failed connection has been replaced and the capture state replayed. Scans
+/// were lost in between.
+#[derive(Debug)]
+pub struct ReconnectEvent {
+    /// The error that revealed the failure.
+    pub cause: UrgError,
+    /// Connection attempts it took, the successful one included.
+    pub attempts: u32,
+    /// Time from noticing the failure to the replayed connection.
+    pub downtime: Duration,
+}
+
+type ReconnectCallback = Box<dyn FnMut(&ReconnectEvent) + Send>;
+
+struct Reconnector<T> {
+    policy: ReconnectPolicy,
+    connect: Box<dyn FnMut() -> io::Result<T> + Send>,
+}
+
+// What a new connection needs to continue where the old one failed, shared with the iterators.
+pub(crate) struct ReconnectState<T> {
+    reconnector: Option<Reconnector<T>>,
+    on_reconnect: Option<ReconnectCallback>,
+    pub(crate) timeouts: UrgTimeouts,
+    pub(crate) motor_speed: Option<MotorSpeed>,
+    pub(crate) high_sensitivity: Option<bool>,
+    // Counts the connections, so that iterators restart their streams on a new one.
+    pub(crate) generation: u64,
+    // The read timeout set on the current connection.
+    read_timeout: Option<Duration>,
+}
+
+impl<T> ReconnectState<T> {
+    pub(crate) fn new(timeouts: UrgTimeouts) -> Self {
+        Self {
+            reconnector: None,
+            on_reconnect: None,
+            timeouts,
+            motor_speed: None,
+            high_sensitivity: None,
+            generation: 0,
+            read_timeout: timeouts.response,
+        }
+    }
+}
+
+impl<T: Transport> ReconnectState<T> {
+    // Sets the read timeout of `transport`, the current connection, unless it is already
+    // `timeout`, so that the frames of a stream do not cost a syscall each.
+    pub(crate) fn set_read_timeout(
+        &mut self,
+        transport: &T,
+        timeout: Option<Duration>,
+    ) -> io::Result<()> {
+        if self.read_timeout != timeout {
+            transport.set_read_timeout(timeout)?;
+            self.read_timeout = timeout;
+        }
+        Ok(())
+    }
+}
+
+impl<T> fmt::Debug for ReconnectState<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectState")
+            .field("policy", &self.reconnector.as_ref().map(|r| r.policy))
+            .field("timeouts", &self.timeouts)
+            .field("motor_speed", &self.motor_speed)
+            .field("high_sensitivity", &self.high_sensitivity)
+            .field("generation", &self.generation)
+            .field("read_timeout", &self.read_timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl UrgError {
+    fn is_connection_error(&self) -> bool {
+        matches!(self, UrgError::Io(_) | UrgError::Timeout { .. })
+    }
+}
+
+impl<T: Transport> Urg<T> {
+    /// Replaces the connection with one from `connect` when a command or a stream fails with an
+    /// I/O error or a timeout. The motor speed, sensitivity and laser state set through this `Urg`
+    /// are replayed, running streams are restarted and the failed command is sent once more.
+    pub fn set_reconnect_policy(
+        &mut self,
+        policy: ReconnectPolicy,
+        connect: impl FnMut() -> io::Result<T> + Send + 'static,
+    ) {
+        lock(&self.reconnect).reconnector = Some(Reconnector {
+            policy,
+            connect: Box::new(connect),
+        });
+    }
+
+    /// Called after each reconnect on the thread that noticed the failure. The connection is
+    /// locked meanwhile, so `callback` must not use this `Urg` or its iterators.
+    pub fn on_reconnect(&mut self, callback: impl FnMut(&ReconnectEvent) + Send + 'static) {
+        lock(&self.reconnect).on_reconnect = Some(Box::new(callback));
+    }
+}
+
+impl Urg {
+    /// `set_reconnect_policy` with new connections to the current peer address.
+    pub fn enable_reconnect(&mut self, policy: ReconnectPolicy) -> Result<(), UrgError> {
+        let address = self.peer_addr()?;
+        let timeout = self.timeouts.connect;
+        self.set_reconnect_policy(policy, move || match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&address, timeout),
+            None => TcpStream::connect(address),
+        });
+        Ok(())
+    }
+}
+
+// Replaces the connection in `reader` after `cause`, retrying with backoff. Returns `cause` when
+// it is not a connection error, there is no policy, every attempt failed or `cancel` got set.
+//
+// Both locks are released while waiting between attempts, and the state lock during each attempt
+// too. Whoever takes the connection meanwhile fails on it too and may reconnect first, which ends
+// this reconnect.
+pub(crate) fn reconnect<'a, T: Transport>(
+    stream: &'a Mutex<BufReader<T>>,
+    mut reader: MutexGuard<'a, BufReader<T>>,
+    state: &Mutex<ReconnectState<T>>,
+    is_capturing: &AtomicBool,
+    time_stamp_tracker: &Mutex<TimeStampTracker>,
+    cancel: Option<&AtomicBool>,
+    cause: UrgError,
+) -> Result<MutexGuard<'a, BufReader<T>>, UrgError> {
+    if !cause.is_connection_error() {
+        return Err(cause);
+    }
+
+    let started = Instant::now();
+    let generation = lock(state).generation;
+    let mut backoff = None;
+    let mut attempts = 0;
+    let mut guard = loop {
+        // Connecting and replaying may take the connect timeout and several response timeouts,
+        // during which only users of the connection wait.
+        let (mut reconnector, timeouts, motor_speed, high_sensitivity) = {
+            let mut guard = lock(state);
+            let Some(reconnector) = guard.reconnector.take() else {
+                return Err(cause);
+            };
+            (
+                reconnector,
+                guard.timeouts,
+                guard.motor_speed,
+                guard.high_sensitivity,
+            )
+        };
+        attempts += 1;
+        let res = (reconnector.connect)()
+            .map_err(UrgError::from)
+            .and_then(|transport| {
+                replay(
+                    transport,
+                    timeouts,
+                    motor_speed,
+                    high_sensitivity,
+                    is_capturing.load(Ordering::Relaxed),
+                )
+            });
+
+        let policy = reconnector.policy;
+        let mut guard = lock(state);
+        // Unless `set_reconnect_policy` replaced it meanwhile.
+        if guard.reconnector.is_none() {
+            guard.reconnector = Some(reconnector);
+        }
+        match res {
+            Ok(new_reader) => {
+                *reader = new_reader;
+                guard.read_timeout = timeouts.response;
+                break guard;
+            }
+            Err(_) if policy.max_attempts.is_some_and(|max| attempts >= max) => {
+                return Err(cause);
+            }
+            Err(_) => {}
+        }
+
+        let wait = backoff.map_or(policy.initial_backoff, |backoff: Duration| {
+            (backoff * 2).min(policy.max_backoff)
+        });
+        backoff = Some(wait);
+        drop(guard);
+        drop(reader);
+        thread::sleep(wait);
+        if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            return Err(cause);
+        }
+        reader = lock(stream);
+        if lock(state).generation != generation {
+            return Ok(reader);
+        }
+    };
+
+    guard.generation += 1;
+    lock(time_stamp_tracker).restart();
+    if let Some(on_reconnect) = guard.on_reconnect.as_mut() {
+        on_reconnect(&ReconnectEvent {
+            cause,
+            attempts,
+            downtime: started.elapsed(),
+        });
+    }
+    drop(guard);
+    Ok(reader)
+}
+
+// Negotiates the protocol on a new connection and restores the settings of the old one.
+fn replay<T: Transport>(
+    transport: T,
+    timeouts: UrgTimeouts,
+    motor_speed: Option<MotorSpeed>,
+    high_sensitivity: Option<bool>,
+    is_capturing: bool,
+) -> Result<BufReader<T>, UrgError> {
+    transport.set_read_timeout(timeouts.response)?;
+    transport.set_write_timeout(timeouts.write)?;
+    let mut reader = BufReader::new(transport);
+    let mut buffer = Vec::new();
+    Urg::<T>::negotiate_protocol(&mut reader, &mut buffer)?;
+
+    let mut cmds = Vec::new();
+    if let Some(speed) = motor_speed {
+        cmds.push(format!("CR{:0>2}", speed.code()?));
+    }
+    if let Some(enable) = high_sensitivity {
+        cmds.push(if enable { "HS1" } else { "HS0" }.to_string());
+    }
+    if is_capturing {
+        cmds.push("BM".to_string());
+    }
+    for cmd in &cmds {
+        match Urg::<T>::send_tagged_cmd(&mut reader, &mut buffer, cmd, "00", None) {
+            Ok(_) => _ = recv_data(&mut reader, &mut buffer, cmd)?,
+            // The sensor kept its state through the outage.
+            Err(UrgError::Status {
+                status: ScipStatus::AlreadyInEffect | ScipStatus::LaserAlreadyOn,
+                ..
+            }) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(reader)
+}
@@ -0,0 +1,227 @@
+use crate::{lock, Transport, UrgError, UrgPayload, UrgPayloadIterator};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{RecvTimeoutError, TryRecvError},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// What the acquisition thread does when the ring buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Discard the oldest scan to make room.
+    DropOldest,
+    /// Wait for a consumer to take a scan. The sensor keeps streaming meanwhile, so frames queue
+    /// up in the connection.
+    Block,
+}
+
+#[derive(Debug)]
+struct State {
+    scans: VecDeque<Arc<UrgPayload>>,
+    latest: Option<Arc<UrgPayload>>,
+    dropped: u64,
+    error: Option<UrgError>,
+    is_running: bool,
+    stop: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+// The longest wait for a frame on a connection without a response timeout. Without a bound, a
+// silent sensor would block the thread in a read, and dropping the `ScanStream` with it.
+const MAX_FRAME_WAIT: Duration = Duration::from_secs(2);
+
+/// Reads a scan stream on a dedicated thread into a bounded ring buffer.
+///
+/// Dropping it stops the thread, which stops the stream with `QT` after the next frame. Without a
+/// response timeout frames are waited for at most 2 s, after which the stream ends with
+/// `UrgError::Timeout`, so that dropping it returns even when the sensor goes silent.
+#[derive(Debug)]
+pub struct ScanStream {
+    shared: Arc<Shared>,
+    cancel_reconnect: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ScanStream {
+    /// Starts reading `scans`, e.g. from `Urg::get_distance_multi` with `num_of_scan == 0`.
+    pub fn spawn<T: Transport + Send + 'static>(
+        mut scans: UrgPayloadIterator<T>,
+        capacity: usize,
+        policy: OverflowPolicy,
+    ) -> Self {
+        let capacity = capacity.max(1);
+        scans.raw.frame_timeout = scans.raw.frame_timeout.or(Some(MAX_FRAME_WAIT));
+        let cancel_reconnect = scans.raw.cancel_reconnect.clone();
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                scans: VecDeque::with_capacity(capacity),
+                latest: None,
+                dropped: 0,
+                error: None,
+                is_running: true,
+                stop: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+        });
+        let thread = thread::Builder::new()
+            .name("urg-scan-stream".to_string())
+            .spawn({
+                let shared = shared.clone();
+                move || acquire(scans, &shared, capacity, policy)
+            })
+            .expect("failed to spawn the scan stream thread");
+
+        Self {
+            shared,
+            cancel_reconnect,
+            thread: Some(thread),
+        }
+    }
+
+    /// The most recent scan, whether or not it has been received.
+    pub fn latest(&self) -> Option<Arc<UrgPayload>> {
+        lock(&self.shared.state).latest.clone()
+    }
+
+    pub fn try_recv(&self) -> Result<Arc<UrgPayload>, TryRecvError> {
+        let mut state = lock(&self.shared.state);
+        match state.scans.pop_front() {
+            Some(scan) => {
+                self.shared.not_full.notify_one();
+                Ok(scan)
+            }
+            None if state.is_running => Err(TryRecvError::Empty),
+            None => Err(TryRecvError::Disconnected),
+        }
+    }
+
+    /// Waits up to `timeout` for a scan. `Disconnected` means the stream has ended and every scan
+    /// has been received; `take_error` tells why.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Arc<UrgPayload>, RecvTimeoutError> {
+        // A timeout too long to represent waits forever.
+        let deadline = Instant::now().checked_add(timeout);
+        let mut state = lock(&self.shared.state);
+        loop {
+            if let Some(scan) = state.scans.pop_front() {
+                self.shared.not_full.notify_one();
+                return Ok(scan);
+            }
+            if !state.is_running {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            state = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    self.shared
+                        .not_empty
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|err| err.into_inner())
+                        .0
+                }
+                None => self
+                    .shared
+                    .not_empty
+                    .wait(state)
+                    .unwrap_or_else(|err| err.into_inner()),
+            };
+        }
+    }
+
+    /// Scans discarded by `OverflowPolicy::DropOldest`.
+    pub fn dropped_count(&self) -> u64 {
+        lock(&self.shared.state).dropped
+    }
+
+    pub fn is_running(&self) -> bool {
+        lock(&self.shared.state).is_running
+    }
+
+    /// The error that ended the stream, if any.
+    pub fn take_error(&self) -> Option<UrgError> {
+        lock(&self.shared.state).error.take()
+    }
+
+    /// Stops the thread and waits for it, returning the error that ended the stream, if any.
+    pub fn stop(mut self) -> Result<(), UrgError> {
+        self.join();
+        match self.take_error() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn join(&mut self) {
+        lock(&self.shared.state).stop = true;
+        self.cancel_reconnect.store(true, Ordering::Relaxed);
+        self.shared.not_full.notify_all();
+        if let Some(thread) = self.thread.take() {
+            _ = thread.join();
+        }
+    }
+}
+
+impl Drop for ScanStream {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+fn acquire<T: Transport>(
+    mut scans: UrgPayloadIterator<T>,
+    shared: &Shared,
+    capacity: usize,
+    policy: OverflowPolicy,
+) {
+    loop {
+        if lock(&shared.state).stop {
+            break;
+        }
+        let scan = match scans.next() {
+            Some(Ok(scan)) => Arc::new(scan),
+            Some(Err(err)) => {
+                lock(&shared.state).error = Some(err);
+                break;
+            }
+            None => break,
+        };
+
+        let mut state = lock(&shared.state);
+        while state.scans.len() >= capacity && !state.stop {
+            match policy {
+                OverflowPolicy::DropOldest => {
+                    state.scans.pop_front();
+                    state.dropped += 1;
+                }
+                OverflowPolicy::Block => {
+                    state = shared
+                        .not_full
+                        .wait(state)
+                        .unwrap_or_else(|err| err.into_inner());
+                }
+            }
+        }
+        state.latest = Some(scan.clone());
+        state.scans.push_back(scan);
+        shared.not_empty.notify_all();
+    }
+
+    // Stops the stream before consumers see the end of it.
+    drop(scans);
+    lock(&shared.state).is_running = false;
+    shared.not_empty.notify_all();
+}
@@ -0,0 +1,391 @@
+use crate::{recv_data, skip_to_echo, InterfaceType, Transport, Urg, UrgError, UrgTimeouts};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Read, Write},
+    mem::MaybeUninit,
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+// The bit rates `SS` accepts.
+const SCIP_BAUD_RATES: [u32; 6] = [19200, 57600, 115200, 250000, 500000, 750000];
+
+/// The SCIP bit rates `SerialPort` can set on this platform. Only Linux sets arbitrary rates; the
+/// others are limited to the standard termios speeds.
+#[cfg(all(
+    target_os = "linux",
+    not(any(target_arch = "powerpc", target_arch = "powerpc64"))
+))]
+pub const SERIAL_BAUD_RATES: &[u32] = &SCIP_BAUD_RATES;
+#[cfg(not(all(
+    target_os = "linux",
+    not(any(target_arch = "powerpc", target_arch = "powerpc64"))
+)))]
+pub const SERIAL_BAUD_RATES: &[u32] = &[19200, 57600, 115200];
+
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+// The most bytes read at one bit rate while looking for the `QT` echo, enough for a few frames of
+// a stale stream.
+const PROBE_SKIP_LIMIT: u64 = 64 * 1024;
+
+/// A tty device (e.g. `/dev/ttyACM0`) configured in raw mode.
+#[derive(Debug)]
+pub struct SerialPort {
+    file: File,
+    // Whether reads give up after a timeout (VMIN == 0) rather than block for a byte.
+    has_timeout: AtomicBool,
+}
+
+impl SerialPort {
+    pub fn open(path: impl AsRef<Path>, baud_rate: u32) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(path)?;
+        let port = Self {
+            file,
+            has_timeout: AtomicBool::new(false),
+        };
+
+        let mut termios = port.get_termios()?;
+        unsafe { libc::cfmakeraw(&mut termios) };
+        termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        port.set_termios(&termios)?;
+        port.set_baud_rate(baud_rate)?;
+        Ok(port)
+    }
+
+    #[cfg(all(
+        target_os = "linux",
+        not(any(target_arch = "powerpc", target_arch = "powerpc64"))
+    ))]
+    pub fn set_baud_rate(&self, baud_rate: u32) -> io::Result<()> {
+        let mut termios = MaybeUninit::<libc::termios2>::uninit();
+        cvt(unsafe {
+            libc::ioctl(
+                self.file.as_raw_fd(),
+                libc::TCGETS2 as _,
+                termios.as_mut_ptr(),
+            )
+        })?;
+        let mut termios = unsafe { termios.assume_init() };
+        termios.c_cflag &= !libc::CBAUD;
+        termios.c_cflag |= libc::BOTHER;
+        termios.c_ispeed = baud_rate;
+        termios.c_ospeed = baud_rate;
+        cvt(unsafe { libc::ioctl(self.file.as_raw_fd(), libc::TCSETS2 as _, &termios) })?;
+        Ok(())
+    }
+
+    #[cfg(not(all(
+        target_os = "linux",
+        not(any(target_arch = "powerpc", target_arch = "powerpc64"))
+    )))]
+    pub fn set_baud_rate(&self, baud_rate: u32) -> io::Result<()> {
+        let speed = match baud_rate {
+            19200 => libc::B19200,
+            57600 => libc::B57600,
+            115200 => libc::B115200,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("baud rate {baud_rate} is not supported on this platform"),
+                ))
+            }
+        };
+        let mut termios = self.get_termios()?;
+        cvt(unsafe { libc::cfsetspeed(&mut termios, speed) })?;
+        self.set_termios(&termios)
+    }
+
+    /// Sets how long a read waits for data. `None` blocks until at least one byte arrives.
+    ///
+    /// The tty resolution is 100 ms and the longest timeout is 25.5 s.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let mut termios = self.get_termios()?;
+        match timeout {
+            Some(timeout) => {
+                let deciseconds = (timeout.as_millis() / 100).clamp(1, u8::MAX as u128);
+                termios.c_cc[libc::VMIN] = 0;
+                termios.c_cc[libc::VTIME] = deciseconds as libc::cc_t;
+            }
+            None => {
+                termios.c_cc[libc::VMIN] = 1;
+                termios.c_cc[libc::VTIME] = 0;
+            }
+        }
+        self.set_termios(&termios)?;
+        self.has_timeout.store(timeout.is_some(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn clear_buffers(&self) -> io::Result<()> {
+        cvt(unsafe { libc::tcflush(self.file.as_raw_fd(), libc::TCIOFLUSH) })?;
+        Ok(())
+    }
+
+    fn get_termios(&self) -> io::Result<libc::termios> {
+        let mut termios = MaybeUninit::uninit();
+        cvt(unsafe { libc::tcgetattr(self.file.as_raw_fd(), termios.as_mut_ptr()) })?;
+        Ok(unsafe { termios.assume_init() })
+    }
+
+    fn set_termios(&self, termios: &libc::termios) -> io::Result<()> {
+        cvt(unsafe { libc::tcsetattr(self.file.as_raw_fd(), libc::TCSANOW, termios) })?;
+        Ok(())
+    }
+}
+
+impl Read for SerialPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.file.read(buf)?;
+        // With VMIN == 0 a tty returns no bytes once VTIME expires. Without a timeout no bytes
+        // means a hangup, e.g. an unplugged adapter, which is left to the caller as end of file.
+        if n == 0 && !buf.is_empty() && self.has_timeout.load(Ordering::Relaxed) {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        Ok(n)
+    }
+}
+
+impl Write for SerialPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Transport for SerialPort {
+    fn interface_type(&self) -> InterfaceType {
+        InterfaceType::Serial
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.set_timeout(timeout)
+    }
+}
+
+impl Urg<SerialPort> {
+    /// Opens a sensor connected to a tty device and switches it to `baud_rate` with `SS`.
+    ///
+    /// The sensor's current bit rate is found by probing each of `SERIAL_BAUD_RATES` with `QT`, and
+    /// a sensor that booted in SCIP 1.1 is switched to SCIP 2.0 first.
+    pub fn open_serial(path: impl AsRef<Path>, baud_rate: u32) -> Result<Self, UrgError> {
+        Self::open_serial_with_timeouts(path, baud_rate, UrgTimeouts::default())
+    }
+
+    /// `open_serial` with `timeouts`, whose `connect` is unused.
+    pub fn open_serial_with_timeouts(
+        path: impl AsRef<Path>,
+        baud_rate: u32,
+        timeouts: UrgTimeouts,
+    ) -> Result<Self, UrgError> {
+        timeouts.validate()?;
+        if !SCIP_BAUD_RATES.contains(&baud_rate) {
+            return Err(UrgError::InvalidArgument(format!(
+                "baud rate {baud_rate} is not supported by SCIP"
+            )));
+        }
+        if !SERIAL_BAUD_RATES.contains(&baud_rate) {
+            return Err(UrgError::InvalidArgument(format!(
+                "baud rate {baud_rate} is not supported on this platform"
+            )));
+        }
+
+        let mut port = SerialPort::open(path, baud_rate)?;
+        port.set_timeout(Some(PROBE_TIMEOUT))?;
+        let current_baud_rate = Self::probe_baud_rate(&mut port, baud_rate)?;
+        port.set_timeout(timeouts.response)?;
+        let mut reader = BufReader::new(port);
+        let mut buffer = Vec::new();
+        let negotiated_protocol = Self::negotiate_protocol(&mut reader, &mut buffer)?;
+        if current_baud_rate != baud_rate {
+            let cmd = format!("SS{baud_rate:0>6}");
+            Self::send_tagged_cmd(&mut reader, &mut buffer, &cmd, "00", None)?;
+            _ = recv_data(&mut reader, &mut buffer, &cmd)?;
+            reader.get_ref().set_baud_rate(baud_rate)?;
+        }
+        Self::with_protocol(reader, negotiated_protocol, timeouts)
+    }
+
+    fn probe_baud_rate(port: &mut SerialPort, preferred: u32) -> Result<u32, UrgError> {
+        let candidates = SERIAL_BAUD_RATES.iter().filter(|rate| **rate != preferred);
+        for &baud_rate in [preferred].iter().chain(candidates) {
+            port.set_baud_rate(baud_rate)?;
+            port.clear_buffers()?;
+            port.write_all(b"QT\n")?;
+            port.flush()?;
+
+            let mut reader = BufReader::new((&mut *port).take(PROBE_SKIP_LIMIT));
+            let mut buffer = Vec::new();
+            // The echo means the bit rate is right, whatever precedes it, such as frames of a
+            // stream left running by an earlier session, and whatever follows it, even an error
+            // status or the single character status of SCIP 1.1.
+            match skip_to_echo(&mut reader, &mut buffer, "QT") {
+                Ok(_) => while recv_data(&mut reader, &mut buffer, "QT")? > 1 {},
+                // Lines up to the limit without the echo, as from a stream at another bit rate.
+                Err(UrgError::Io(_)) if reader.get_ref().limit() == 0 => continue,
+                Err(err @ UrgError::Io(_)) => return Err(err),
+                Err(_) => continue,
+            }
+            return Ok(baud_rate);
+        }
+        // The sensor did not respond at any bit rate.
+        Err(UrgError::Timeout {
+            cmd: "QT".to_string(),
+        })
+    }
+}
+
+fn cvt(res: libc::c_int) -> io::Result<libc::c_int> {
+    if res == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res)
+    }
+}
+
+// The sensor mock reads the bit rate of the pty with `TCGETS2`, which only Linux has.
+#[cfg(all(
+    test,
+    target_os = "linux",
+    not(any(target_arch = "powerpc", target_arch = "powerpc64"))
+))]
+mod test {
+    use super::cvt;
+    use crate::{test::response, Urg, UrgError, UrgTimeouts};
+    use std::{
+        ffi::CStr,
+        fs::File,
+        io::{BufRead, BufReader, Write},
+        mem::MaybeUninit,
+        os::unix::io::{AsRawFd, FromRawFd},
+        ptr, thread,
+        time::Duration,
+    };
+
+    // Answers like a sensor listening at 19200 bps, reading the slave's bit rate through the master.
+    // The first `stale_frames` frames of an `MD` stream left running precede the answer to the
+    // first `QT`. Runs until the slave side is closed.
+    fn run_sensor(master: File, mut stale_frames: usize) -> Vec<String> {
+        let mut writer = master.try_clone().unwrap();
+        let mut reader = BufReader::new(master);
+        let mut sensor_baud_rate = 19200;
+        let mut received = Vec::new();
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap_or(0) > 0 {
+            let (cmd, tag) = match line.trim_end().split_once(';') {
+                Some((cmd, tag)) => (cmd.to_string(), format!(";{tag}")),
+                None => (line.trim_end().to_string(), String::new()),
+            };
+            line.clear();
+            received.push(cmd.clone());
+
+            let mut termios = MaybeUninit::<libc::termios2>::uninit();
+            cvt(unsafe {
+                libc::ioctl(writer.as_raw_fd(), libc::TCGETS2 as _, termios.as_mut_ptr())
+            })
+            .unwrap();
+            if unsafe { termios.assume_init() }.c_ospeed != sensor_baud_rate {
+                continue;
+            }
+
+            let echo = format!("{cmd}{tag}");
+            let mut res = String::new();
+            if cmd == "QT" {
+                for _ in 0..stale_frames {
+                    res += &response("MD0000000201000", "99", &["1Dh0", "1Dh1Dh1Dh"]);
+                }
+                stale_frames = 0;
+            }
+            res += &match cmd.as_str() {
+                "QT" => response(&echo, "00", &[]),
+                "SS115200" => {
+                    sensor_baud_rate = 115200;
+                    response(&echo, "00", &[])
+                }
+                "VV" => response(
+                    &echo,
+                    "00",
+                    &[
+                        "VEND:Hokuyo Automatic Co.,Ltd.;",
+                        "PROD:SOKUIKI Sensor URG-04LX;",
+                        "FIRM:3.3.00,08/04/16(20-4095[mm],240[deg]);",
+                        "PROT:SCIP 2.0;",
+                        "SERI:H0000000;",
+                    ],
+                ),
+                _ => response(&echo, "0E", &[]),
+            };
+            writer.write_all(res.as_bytes()).unwrap();
+        }
+        received
+    }
+
+    // Returns the master and slave sides of a new pty and the path of the slave.
+    fn open_pty() -> (File, File, String) {
+        let (mut master, mut slave) = (0, 0);
+        cvt(unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                ptr::null_mut(),
+                ptr::null(),
+                ptr::null(),
+            )
+        })
+        .unwrap();
+        let master = unsafe { File::from_raw_fd(master) };
+        let slave = unsafe { File::from_raw_fd(slave) };
+        let path = unsafe { CStr::from_ptr(libc::ttyname(slave.as_raw_fd())) }
+            .to_str()
+            .unwrap()
+            .to_string();
+        (master, slave, path)
+    }
+
+    #[test]
+    fn open_serial_pty_test() {
+        let (master, slave, path) = open_pty();
+        // 230400 is a common tty rate, but not one of SCIP's.
+        match Urg::open_serial(&path, 230400) {
+            Err(UrgError::InvalidArgument(_)) => {}
+            res => panic!("unexpected result {res:?}"),
+        }
+
+        let sensor = thread::spawn(move || run_sensor(master, 0));
+        let timeouts = UrgTimeouts {
+            response: Some(Duration::from_secs(1)),
+            ..UrgTimeouts::default()
+        };
+        let urg = Urg::open_serial_with_timeouts(&path, 115200, timeouts).unwrap();
+        assert_eq!(urg.timeouts(), timeouts);
+        drop(urg);
+        drop(slave);
+        let received = sensor.join().unwrap();
+        assert_eq!(received, ["QT", "QT", "VV", "SS115200", "VV"]);
+    }
+
+    #[test]
+    fn open_serial_stale_stream_pty_test() {
+        let (master, slave, path) = open_pty();
+        let sensor = thread::spawn(move || run_sensor(master, 2));
+        let timeouts = UrgTimeouts {
+            response: Some(Duration::from_secs(1)),
+            ..UrgTimeouts::default()
+        };
+        let urg = Urg::open_serial_with_timeouts(&path, 115200, timeouts).unwrap();
+        drop(urg);
+        drop(slave);
+        let received = sensor.join().unwrap();
+        assert_eq!(received, ["QT", "QT", "VV", "SS115200", "VV"]);
+    }
+}
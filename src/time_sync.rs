@@ -0,0 +1,338 @@
+use crate::{get_raw_data, lock, recv_data, Transport, Urg, UrgError};
+use std::time::{Duration, Instant, SystemTime};
+
+const TIME_STAMP_RANGE: i64 = 1 << 24;
+
+/// Extends the sensor's 24-bit millisecond time stamp, which wraps about every 4.6 hours, into a
+/// monotonically increasing 64-bit time.
+#[derive(Debug, Clone, Default)]
+pub struct TimeStampTracker {
+    latest: u64,
+    // The extended time stamp of the last update and when it was made.
+    last_update: Option<(u64, Instant)>,
+    // Extended time at which the counter last restarted from the sensor's point of view.
+    epoch: u64,
+    may_have_restarted: bool,
+}
+
+impl TimeStampTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, time_stamp: u32) -> u64 {
+        self.update_at(time_stamp, Instant::now())
+    }
+
+    /// `update` with a time stamp received at `now`.
+    ///
+    /// The host time elapsed since the last update tells how many wraps lie in between, so gaps
+    /// of any length between single-shot scans are extended correctly.
+    pub fn update_at(&mut self, time_stamp: u32, now: Instant) -> u64 {
+        let range = TIME_STAMP_RANGE as u64;
+        let time_stamp = time_stamp as u64 % range;
+        let extended = match self.last_update {
+            Some(_)
+                if std::mem::take(&mut self.may_have_restarted)
+                    && time_stamp < (self.latest - self.epoch) % range =>
+            {
+                // The sensor restarted its counter, so this continues from the latest time.
+                self.epoch = self.latest;
+                self.latest + time_stamp
+            }
+            Some((last, instant)) => {
+                // The sensor counts milliseconds like the host, so the time stamp falls in the wrap
+                // nearest to where the host clock expects it. Jitter and late frames stay a little
+                // before the latest time rather than a whole wrap later.
+                let elapsed = now.saturating_duration_since(instant).as_millis();
+                let expected = last.saturating_add(u64::try_from(elapsed).unwrap_or(u64::MAX));
+                let wraps = (expected.saturating_sub(self.epoch + time_stamp) + range / 2) / range;
+                self.epoch + wraps * range + time_stamp
+            }
+            None => self.epoch + time_stamp,
+        };
+        self.latest = self.latest.max(extended);
+        self.last_update = Some((extended, now));
+        extended
+    }
+
+    /// The largest extended time stamp seen so far.
+    pub fn latest(&self) -> u64 {
+        self.latest
+    }
+
+    // After a reconnect the sensor may have rebooted, in which case its counter goes backwards.
+    pub(crate) fn restart(&mut self) {
+        self.may_have_restarted = true;
+    }
+}
+
+/// Relation between the sensor's millisecond counter and the host clocks, estimated from `TM1`
+/// round trips.
+///
+/// The offset comes from the round trip with the least delay. Samples of one `sync_time` are only
+/// milliseconds apart, far too close to measure drift, so it starts at 1.0 and is refined with
+/// `update_drift` from an earlier sync.
+#[derive(Debug, Clone)]
+pub struct UrgTimeSync {
+    base_instant: Instant,
+    base_system_time: SystemTime,
+    // The extended time stamp the sensor read at `base_instant`.
+    base_time_stamp: u64,
+    drift: f64,
+    pub min_round_trip: Duration,
+    pub sample_count: usize,
+}
+
+impl UrgTimeSync {
+    /// Sensor milliseconds elapsed per host millisecond.
+    pub fn drift(&self) -> f64 {
+        self.drift
+    }
+
+    /// Estimates the drift between `earlier` and this sync; the further apart, the more
+    /// accurate. An estimate off by more than 0.1%, far beyond any crystal, means the syncs were
+    /// too close or the counter restarted, and is ignored.
+    pub fn update_drift(&mut self, earlier: &UrgTimeSync) {
+        let host_ms = self
+            .base_instant
+            .saturating_duration_since(earlier.base_instant)
+            .as_secs_f64()
+            * 1000.0;
+        let sensor_ms = self.base_time_stamp as f64 - earlier.base_time_stamp as f64;
+        let drift = sensor_ms / host_ms;
+        if (drift - 1.0).abs() <= 1e-3 {
+            self.drift = drift;
+        }
+    }
+
+    /// The host instant of a raw time stamp, taking the wrap nearest to the sync. `None` if it
+    /// lies before the host clock can tell.
+    pub fn to_instant(&self, time_stamp: u32) -> Option<Instant> {
+        self.to_instant_extended(self.extend(time_stamp)?)
+    }
+
+    pub fn to_system_time(&self, time_stamp: u32) -> Option<SystemTime> {
+        self.to_system_time_extended(self.extend(time_stamp)?)
+    }
+
+    /// Like `to_instant`, for an `extended_time_stamp` of the same `Urg`, which stays exact any
+    /// number of wraps away from the sync.
+    pub fn to_instant_extended(&self, extended_time_stamp: u64) -> Option<Instant> {
+        let (offset, is_later) = self.host_offset(extended_time_stamp)?;
+        if is_later {
+            self.base_instant.checked_add(offset)
+        } else {
+            self.base_instant.checked_sub(offset)
+        }
+    }
+
+    pub fn to_system_time_extended(&self, extended_time_stamp: u64) -> Option<SystemTime> {
+        let (offset, is_later) = self.host_offset(extended_time_stamp)?;
+        if is_later {
+            self.base_system_time.checked_add(offset)
+        } else {
+            self.base_system_time.checked_sub(offset)
+        }
+    }
+
+    // The extended time stamp of the wrap nearest to the sync.
+    fn extend(&self, time_stamp: u32) -> Option<u64> {
+        let base = self.base_time_stamp as i64;
+        let diff = (time_stamp as i64 - base).rem_euclid(TIME_STAMP_RANGE);
+        let diff = if diff >= TIME_STAMP_RANGE / 2 {
+            diff - TIME_STAMP_RANGE
+        } else {
+            diff
+        };
+        u64::try_from(base + diff).ok()
+    }
+
+    // Host time from `base_instant` to when the sensor read `extended_time_stamp`, and whether it
+    // is later.
+    fn host_offset(&self, extended_time_stamp: u64) -> Option<(Duration, bool)> {
+        let sensor_ms = extended_time_stamp as f64 - self.base_time_stamp as f64;
+        let host_ms = sensor_ms / self.drift;
+        let offset = Duration::try_from_secs_f64(host_ms.abs() / 1000.0).ok()?;
+        Some((offset, host_ms >= 0.0))
+    }
+
+    // `samples` holds (host ms since `base_instant` halfway through, extended sensor time stamp,
+    // round trip) of each `TM1`. Queueing only ever delays a round trip, so the quickest one
+    // bounds the offset best.
+    fn estimate(
+        base_instant: Instant,
+        base_system_time: SystemTime,
+        samples: &[(f64, u64, Duration)],
+    ) -> Self {
+        let (host_ms, time_stamp, round_trip) = *samples
+            .iter()
+            .min_by_key(|(_, _, round_trip)| *round_trip)
+            .unwrap();
+        let offset = Duration::from_secs_f64(host_ms / 1000.0);
+
+        Self {
+            base_instant: base_instant + offset,
+            base_system_time: base_system_time + offset,
+            base_time_stamp: time_stamp,
+            drift: 1.0,
+            min_round_trip: round_trip,
+            sample_count: samples.len(),
+        }
+    }
+}
+
+impl<T: Transport> Urg<T> {
+    /// Enters the time adjust mode with `TM0`, reads the sensor time `rounds` times with `TM1`
+    /// and leaves with `TM2`.
+    pub fn sync_time(&self, rounds: usize) -> Result<UrgTimeSync, UrgError> {
+        let rounds = rounds.max(1);
+        self.with_reader(|reader, buffer| {
+            self.send_cmd(reader, buffer, "TM0", "00")?;
+            _ = recv_data(reader, buffer, "TM0")?;
+
+            let base_system_time = SystemTime::now();
+            let base_instant = Instant::now();
+            let mut samples = Vec::with_capacity(rounds);
+            let mut res = Ok(());
+            for _ in 0..rounds {
+                let sent = Instant::now();
+                res = self
+                    .send_cmd(reader, buffer, "TM1", "00")
+                    .and_then(|_| get_raw_data(reader, buffer, "TM1"))
+                    .map(|(time_stamp, _)| {
+                        let time_stamp = lock(&self.time_stamp_tracker).update(time_stamp);
+                        let received = Instant::now();
+                        let round_trip = received - sent;
+                        let host_ms = (sent - base_instant).as_secs_f64() * 1000.0
+                            + round_trip.as_secs_f64() * 500.0;
+                        samples.push((host_ms, time_stamp, round_trip));
+                    });
+                if res.is_err() {
+                    break;
+                }
+            }
+
+            self.send_cmd(reader, buffer, "TM2", "00")?;
+            _ = recv_data(reader, buffer, "TM2")?;
+            res?;
+
+            Ok(UrgTimeSync::estimate(
+                base_instant,
+                base_system_time,
+                &samples,
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TimeStampTracker, UrgTimeSync};
+    use std::time::{Duration, Instant, SystemTime};
+
+    #[test]
+    fn time_stamp_tracker_test() {
+        let mut tracker = TimeStampTracker::new();
+        assert_eq!(tracker.update(16777000), 16777000);
+        assert_eq!(tracker.update(16777200), 16777200);
+        assert_eq!(tracker.update(100), 16777316);
+        // A late frame from before the wrap keeps its place.
+        assert_eq!(tracker.update(16777210), 16777210);
+        assert_eq!(tracker.update(90), 16777306);
+        assert_eq!(tracker.update(300), 16777516);
+        assert_eq!(tracker.latest(), 16777516);
+
+        // A sensor that rebooted during a reconnect starts its counter again.
+        tracker.restart();
+        assert_eq!(tracker.update(40), 16777556);
+        assert_eq!(tracker.update(50), 16777566);
+        tracker.restart();
+        assert_eq!(tracker.update(60), 16777576);
+    }
+
+    #[test]
+    fn time_stamp_tracker_long_gap_test() {
+        const HOUR: u64 = 3600 * 1000;
+        let range = 1 << 24;
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        // Single-shot scans three hours apart, more than half the range.
+        let mut tracker = TimeStampTracker::new();
+        for i in 0..4 {
+            let time = 100 + i * 3 * HOUR;
+            assert_eq!(tracker.update_at((time % range) as u32, at(time)), time);
+        }
+        // Ten hours, more than two wraps, with the host clock a second off.
+        let time = 100 + 19 * HOUR;
+        let time_stamp = (time % range) as u32;
+        assert_eq!(tracker.update_at(time_stamp, at(time + 1000)), time);
+        assert_eq!(tracker.latest(), time);
+    }
+
+    #[test]
+    fn estimate_test() {
+        let base_instant = Instant::now();
+        let base_system_time = SystemTime::now();
+        // Back-to-back `TM1` a few milliseconds apart. Jitter delays the requests while replies
+        // take 1 ms, and the sensor's 1 ms counter runs 50 ppm fast.
+        let sample = |host_ms: f64, delay: f64| {
+            let sensor_ms = 16777000.0 + (host_ms + delay - 1.0) * 1.00005;
+            (
+                host_ms + delay / 2.0,
+                sensor_ms as u64,
+                Duration::from_secs_f64(delay / 1000.0),
+            )
+        };
+        let samples: Vec<_> = [7.0, 3.0, 2.0, 9.0, 4.0]
+            .iter()
+            .enumerate()
+            .map(|(i, delay)| sample(i as f64 * 10.0, *delay))
+            .collect();
+        let sync = UrgTimeSync::estimate(base_instant, base_system_time, &samples);
+        assert_eq!(sync.drift(), 1.0);
+        assert_eq!(sync.min_round_trip, Duration::from_millis(2));
+
+        // A minute later the error stays within the time stamp resolution and the drift.
+        let time_stamp = (16777000.0 + 60021.0 * 1.00005) as u64;
+        let error = |instant: Instant, expected: Instant| {
+            if instant > expected {
+                instant - expected
+            } else {
+                expected - instant
+            }
+        };
+        let expected = base_instant + Duration::from_millis(60021);
+        let instant = sync.to_instant_extended(time_stamp).unwrap();
+        assert!(error(instant, expected) < Duration::from_millis(5));
+        // The raw time stamp has wrapped since the sync.
+        let instant = sync.to_instant((time_stamp % (1 << 24)) as u32).unwrap();
+        assert!(error(instant, expected) < Duration::from_millis(5));
+
+        // Ten minutes later, a second sync measures the drift.
+        let later: Vec<_> = [5.0, 2.0, 6.0]
+            .iter()
+            .enumerate()
+            .map(|(i, delay)| sample(600000.0 + i as f64 * 10.0, *delay))
+            .collect();
+        let mut later_sync = UrgTimeSync::estimate(base_instant, base_system_time, &later);
+        later_sync.update_drift(&sync);
+        assert!((later_sync.drift() - 1.00005).abs() < 1e-5);
+
+        let system_time = sync.to_system_time_extended(16777021).unwrap();
+        let error = system_time
+            .duration_since(base_system_time + Duration::from_millis(21))
+            .unwrap_or_else(|err| err.duration());
+        assert!(error < Duration::from_millis(2));
+
+        // Neither panics long before the host clock started.
+        let mut early_sync = sync.clone();
+        early_sync.base_time_stamp = u64::MAX;
+        _ = early_sync.to_instant_extended(0);
+        _ = early_sync.to_system_time_extended(0);
+        // The nearest wrap lies before the first time stamp.
+        early_sync.base_time_stamp = 1000;
+        assert_eq!(early_sync.to_instant(16777000), None);
+    }
+}